@@ -1,7 +1,10 @@
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
 use clap::{
-    app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, SubCommand,
+    app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches,
+    SubCommand,
 };
+use solana_account_decoder::UiAccountEncoding;
 #[allow(unused_imports)]
 use solana_clap_utils::{
     input_parsers::{pubkey_of, value_of},
@@ -10,10 +13,13 @@ use solana_clap_utils::{
 };
 #[allow(unused_imports)]
 use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
 #[allow(unused_imports)]
 use solana_sdk::borsh::try_from_slice_unchecked;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::program_pack::Pack;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{read_keypair_file, Signer};
 #[allow(unused_imports)]
@@ -24,12 +30,21 @@ use solana_sdk::system_program;
 use solana_sdk::transaction::Transaction;
 use spl_associated_token_account;
 use spl_token;
+use std::mem::size_of;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+const YEAR: u64 = 31_556_926;
 const PROGRAM_ID: &str = "SCYBRhsVKNUvM7JKnXHBYMBLmt6cbichmT8iugAcsmU";
 const VAULT_SEED: &[u8; 8] = b"___vault";
+const RECEIPT_SEED: &[u8; 8] = b"_receipt";
+const NFT_VAULT_SEED: &[u8; 8] = b"nftvault";
+const REFERRAL_SEED: &[u8; 8] = b"referral";
+const MULTISIG_SEED: &[u8; 8] = b"multisig";
+const MAX_SIGNERS: usize = 10;
 const MINT: &str = "SCYfrGCw8aDiqdgcpdGjV6jp4UVVQLuphxTDLNWu36f";
 #[allow(dead_code)]
 const ADMIN_PK: &str = "SCYXfY5QqYihdX931asDQcRuury6NFL6WtX3UsWbdCz";
+const METADATA_PROGRAM_ID: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
 
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 enum StakeInstruction {
@@ -42,39 +57,231 @@ enum StakeInstruction {
         rate: u64,
         #[allow(dead_code)]
         early_withdrawal_fee: u64,
+        #[allow(dead_code)]
+        position_nft: bool,
+        #[allow(dead_code)]
+        receipt_token: bool,
+        #[allow(dead_code)]
+        share_accounting: bool,
+        #[allow(dead_code)]
+        boost_collection: Pubkey,
+        #[allow(dead_code)]
+        boost_bps: u64,
+        #[allow(dead_code)]
+        referral_bps: u64,
+        #[allow(dead_code)]
+        linear_accrual: bool,
+        #[allow(dead_code)]
+        lock_policy: u8,
+        #[allow(dead_code)]
+        rollover_policy: u8,
     },
     Stake {
         #[allow(dead_code)]
         amount: u64,
+        #[allow(dead_code)]
+        referrer: Option<Pubkey>,
     },
     Unstake,
     Withdraw {
         #[allow(dead_code)]
         amount: u64,
     },
+    TransferPosition,
+    GenerateNftVault {
+        #[allow(dead_code)]
+        collection: Pubkey,
+        #[allow(dead_code)]
+        min_period: u64,
+        #[allow(dead_code)]
+        reward_period: u64,
+        #[allow(dead_code)]
+        rate: u64,
+        #[allow(dead_code)]
+        linear_accrual: bool,
+        #[allow(dead_code)]
+        early_withdrawal_fee: u64,
+    },
+    StakeNft,
+    UnstakeNft,
+    ClaimReferral,
+    MigrateAccount,
+    SweepToken {
+        #[allow(dead_code)]
+        amount: u64,
+    },
+    SetEmergency {
+        #[allow(dead_code)]
+        enabled: bool,
+    },
+    EmergencyUnstake,
+    SetPause {
+        #[allow(dead_code)]
+        paused: u8,
+    },
+    SetMultisig {
+        #[allow(dead_code)]
+        signers: Vec<Pubkey>,
+        #[allow(dead_code)]
+        threshold: u8,
+    },
 }
 
-#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+enum AccountKey {
+    Uninitialized,
+    StakeV1,
+    VaultV1,
+    ReferralV1,
+    MultisigV1,
+}
+
+// VaultData.paused bits
+const PAUSE_STAKE: u8 = 1 << 0;
+const PAUSE_CLAIM: u8 = 1 << 1;
+const PAUSE_UNSTAKE: u8 = 1 << 2;
+const PAUSE_WITHDRAW: u8 = 1 << 3;
+
+// VaultData.lock_policy values, by their CLI names
+const LOCK_POLICIES: [&str; 3] = ["extend", "keep", "weighted"];
+
+// VaultData.rollover_policy values, by their CLI names
+const ROLLOVER_POLICIES: [&str; 3] = ["compound", "payout", "pending"];
+
+// fixed offsets into StakeData, for memcmp filters
+const STAKE_ACTIVE_OFFSET: usize = 1;
+const STAKE_STAKER_OFFSET: usize = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
 struct StakeData {
-    timestamp: u64,
-    staker: Pubkey,
-    mint: Pubkey,
-    active: bool,
+    key: u8,
+    active: u8,
+    bump: u8,
+    padding: [u8; 5],
+    last_accrual: u64,
     withdrawn: u64,
     harvested: u64,
     staked_amount: u64,
     max_reward: u64,
+    shares: u64,
+    boost_bps: u64,
+    staker: Pubkey,
+    mint: Pubkey,
+    position_mint: Pubkey,
+    referrer: Pubkey,
+    rate: u64,
+    maturity: u64,
+    lock_start: u64,
+    unlock_at: u64,
+    pending_reward: u64,
+    reserved: [u8; 24],
 }
 
-#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
 struct VaultData {
-    mint: Pubkey,
+    key: u8,
+    position_nft: u8,
+    share_accounting: u8,
+    bump: u8,
+    emergency: u8,
+    paused: u8,
+    linear_accrual: u8,
+    lock_policy: u8,
     min_period: u64,
     reward_period: u64,
     rate: u64,
     early_withdrawal_fee: u64,
     total_obligations: u64,
     total_staked: u64,
+    total_shares: u64,
+    last_accrual: u64,
+    boost_bps: u64,
+    referral_bps: u64,
+    mint: Pubkey,
+    receipt_mint: Pubkey,
+    collection: Pubkey,
+    boost_collection: Pubkey,
+    multisig: Pubkey,
+    rollover_policy: u8,
+    padding: [u8; 7],
+    pool_reserve: u64,
+    reserved: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+struct ReferralData {
+    key: u8,
+    bump: u8,
+    padding: [u8; 6],
+    pending: u64,
+    total_earned: u64,
+    referrer: Pubkey,
+    reserved: [u8; 64],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+struct MultisigData {
+    key: u8,
+    threshold: u8,
+    signer_count: u8,
+    bump: u8,
+    padding: [u8; 4],
+    vault: Pubkey,
+    signers: [Pubkey; MAX_SIGNERS],
+    reserved: [u8; 64],
+}
+
+fn unpack_account<T: Pod>(data: &[u8]) -> T {
+    bytemuck::pod_read_unaligned(&data[..size_of::<T>()])
+}
+
+// Mirrors the program's accrual: `reward` is earned every `per` seconds, counting only whole
+// reward periods unless the vault accrues linearly.
+fn estimate_reward(vault_data: &VaultData, reward: u64, per: u64, elapsed: u64) -> u64 {
+    let counted = if vault_data.linear_accrual != 0 {
+        elapsed
+    } else {
+        elapsed - elapsed % vault_data.reward_period.max(1)
+    };
+
+    (reward as u128 * counted as u128 / per.max(1) as u128) as u64
+}
+
+fn cosigner_keypairs(matches: &ArgMatches) -> Vec<Keypair> {
+    matches
+        .values_of("cosign")
+        .map(|paths| {
+            paths
+                .map(|path| read_keypair_file(path).expect("Can't open cosigner file-wallet"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Accounts an admin instruction needs once the vault has a multisig: the multisig account and
+// the co-signers. Vaults without one still take the admin signature alone.
+fn multisig_accounts(
+    client: &RpcClient,
+    vault: &Pubkey,
+    cosigners: &[Keypair],
+) -> Vec<AccountMeta> {
+    let raw_vault_data = client.get_account_data(vault).unwrap();
+    let vault_data = unpack_account::<VaultData>(&raw_vault_data[..]);
+    if vault_data.multisig == Pubkey::default() {
+        return vec![];
+    }
+
+    std::iter::once(AccountMeta::new_readonly(vault_data.multisig, false))
+        .chain(
+            cosigners
+                .iter()
+                .map(|cosigner| AccountMeta::new_readonly(cosigner.pubkey(), true)),
+        )
+        .collect()
 }
 
 fn main() {
@@ -88,6 +295,13 @@ fn main() {
                         .required(true)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("payer")
+                        .short("f")
+                        .long("payer")
+                        .required(false)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("env")
                         .short("e")
@@ -122,6 +336,70 @@ fn main() {
                         .long("early_withdrawal_fee")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("position_nft")
+                        .short("n")
+                        .long("position_nft")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("receipt_token")
+                        .short("t")
+                        .long("receipt_token")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("linear_accrual")
+                        .short("l")
+                        .long("linear_accrual")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("lock_policy")
+                        .long("lock_policy")
+                        .possible_values(&LOCK_POLICIES)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rollover_policy")
+                        .long("rollover_policy")
+                        .possible_values(&ROLLOVER_POLICIES)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("share_accounting")
+                        .short("a")
+                        .long("share_accounting")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("boost_collection")
+                        .short("b")
+                        .long("boost_collection")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("boost_bps")
+                        .short("x")
+                        .long("boost_bps")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("referral_bps")
+                        .short("p")
+                        .long("referral_bps")
+                        .required(false)
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -133,6 +411,13 @@ fn main() {
                         .required(true)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("payer")
+                        .short("f")
+                        .long("payer")
+                        .required(false)
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("env")
                         .short("e")
@@ -149,6 +434,22 @@ fn main() {
                         .allow_hyphen_values(true)
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("boost_nft")
+                        .short("b")
+                        .long("boost_nft")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("referrer")
+                        .short("r")
+                        .long("referrer")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -166,6 +467,181 @@ fn main() {
                         .long("env")
                         .required(false)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("position")
+                        .short("n")
+                        .long("position")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("boost_nft")
+                        .short("b")
+                        .long("boost_nft")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("owner")
+                        .short("o")
+                        .long("owner")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("emergency")
+                        .short("x")
+                        .long("emergency")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-emergency")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nft")
+                        .short("n")
+                        .long("nft")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("off")
+                        .short("o")
+                        .long("off")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("cosign")
+                        .long("cosign")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-pause")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nft")
+                        .short("n")
+                        .long("nft")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("stake")
+                        .long("stake")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("claim")
+                        .long("claim")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("unstake")
+                        .long("unstake")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("withdraw")
+                        .long("withdraw")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("cosign")
+                        .long("cosign")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("set-multisig")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nft")
+                        .short("n")
+                        .long("nft")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("signer")
+                        .long("signer")
+                        .validator(is_pubkey)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("threshold")
+                        .short("t")
+                        .long("threshold")
+                        .validator(is_parsable::<u8>)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cosign")
+                        .long("cosign")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -193,41 +669,1052 @@ fn main() {
                         .value_name("AMOUNT")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nft")
+                        .short("n")
+                        .long("nft")
+                        .required(false)
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("cosign")
+                        .long("cosign")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("transfer-position")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("payer")
+                        .short("f")
+                        .long("payer")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("new_owner")
+                        .short("n")
+                        .long("new_owner")
+                        .validator(is_pubkey)
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("generate_nft_vault")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("payer")
+                        .short("f")
+                        .long("payer")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("collection")
+                        .short("c")
+                        .long("collection")
+                        .validator(is_pubkey)
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("min_period")
+                        .short("m")
+                        .long("min_period")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("reward_period")
+                        .short("p")
+                        .long("reward_period")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .short("r")
+                        .long("rate")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("early_withdrawal_fee")
+                        .short("w")
+                        .long("early_withdrawal_fee")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("linear_accrual")
+                        .short("l")
+                        .long("linear_accrual")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stake-nft")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("payer")
+                        .short("f")
+                        .long("payer")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mint")
+                        .short("m")
+                        .long("mint")
+                        .validator(is_pubkey)
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("unstake-nft")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mint")
+                        .short("m")
+                        .long("mint")
+                        .validator(is_pubkey)
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("claim-referral")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .short("a")
+                        .long("address")
+                        .validator(is_pubkey)
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sweep")
+                .arg(
+                    Arg::with_name("sign")
+                        .short("s")
+                        .long("sign")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mint")
+                        .short("m")
+                        .long("mint")
+                        .validator(is_pubkey)
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("destination")
+                        .short("d")
+                        .long("destination")
+                        .validator(is_pubkey)
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("amount")
+                        .short("a")
+                        .validator(is_amount)
+                        .long("amount")
+                        .value_name("AMOUNT")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cosign")
+                        .long("cosign")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vault")
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nft")
+                        .short("n")
+                        .long("nft")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stake-data")
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("address")
+                        .short("p")
+                        .long("pubkey")
+                        .required(true)
+                        .takes_value(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stakes")
+                .arg(
+                    Arg::with_name("env")
+                        .short("e")
+                        .long("env")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("staker")
+                        .short("s")
+                        .long("staker")
+                        .required(false)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("active")
+                        .short("a")
+                        .long("active")
+                        .required(false)
+                        .takes_value(false),
+                ),
+        )
+        .get_matches();
+
+    let program_id = PROGRAM_ID.parse::<Pubkey>().unwrap();
+    let mint_pk = MINT.parse::<Pubkey>().unwrap();
+
+    if let Some(matches) = matches.subcommand_matches("withdraw") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+
+        let amount = spl_token::ui_amount_to_amount(
+            matches.value_of("amount").unwrap().parse::<u64>().unwrap() as f64,
+            9,
+        );
+        println!("{}", amount);
+
+        let vault_seed: &[u8] = if matches.is_present("nft") {
+            NFT_VAULT_SEED
+        } else {
+            VAULT_SEED
+        };
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[vault_seed], &program_id);
+        let admin_token_address =
+            spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
+        let vault_token_address =
+            spl_associated_token_account::get_associated_token_address(&vault, &mint_pk);
+
+        let cosigners = cosigner_keypairs(matches);
+        let mut accounts = vec![
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new(admin_token_address, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(vault_token_address, false),
+            AccountMeta::new_readonly(mint_pk, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        accounts.extend(multisig_accounts(&client, &vault, &cosigners));
+
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::Withdraw { amount },
+            accounts,
+        )];
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        let mut signers = vec![&wallet_keypair];
+        signers.extend(cosigners.iter());
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("unstake") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+        let staker_token_account =
+            spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
+        let vault_token_account =
+            spl_associated_token_account::get_associated_token_address(&vault, &mint_pk);
+        let position_mint = pubkey_of(matches, "position");
+        // redeeming another staker's position in a receipt vault burns the signer's receipts
+        let owner = pubkey_of(matches, "owner").unwrap_or(wallet_pubkey);
+        let stake_seed = position_mint.unwrap_or(owner);
+        let (stake_data, _) = Pubkey::find_program_address(&[&stake_seed.to_bytes()], &program_id);
+
+        let mut accounts = vec![
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new(stake_data, false),
+            AccountMeta::new(staker_token_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(vault_token_account, false),
+            AccountMeta::new_readonly(mint_pk, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        if let Some(position_mint) = position_mint {
+            accounts.push(AccountMeta::new(position_mint, false));
+            accounts.push(AccountMeta::new(
+                spl_associated_token_account::get_associated_token_address(
+                    &wallet_pubkey,
+                    &position_mint,
+                ),
+                false,
+            ));
+        }
+        let raw_vault_data = client.get_account_data(&vault).unwrap();
+        let vault_data = unpack_account::<VaultData>(&raw_vault_data[..]);
+        if vault_data.receipt_mint != Pubkey::default() {
+            accounts.push(AccountMeta::new(vault_data.receipt_mint, false));
+            accounts.push(AccountMeta::new(
+                spl_associated_token_account::get_associated_token_address(
+                    &wallet_pubkey,
+                    &vault_data.receipt_mint,
+                ),
+                false,
+            ));
+        }
+        // an emergency exit forfeits the reward, so referral and boost accounts aren't needed
+        let emergency = matches.is_present("emergency");
+        let raw_stake_data = client.get_account_data(&stake_data).unwrap();
+        let stake_info = unpack_account::<StakeData>(&raw_stake_data[..]);
+        if stake_info.referrer != Pubkey::default() && !emergency {
+            let (referral, _) = Pubkey::find_program_address(
+                &[REFERRAL_SEED, &stake_info.referrer.to_bytes()],
+                &program_id,
+            );
+            accounts.push(AccountMeta::new(referral, false));
+        }
+        if let Some(boost_mint) = pubkey_of(matches, "boost_nft").filter(|_| !emergency) {
+            let metadata_program_id = METADATA_PROGRAM_ID.parse::<Pubkey>().unwrap();
+            let (boost_metadata, _) = Pubkey::find_program_address(
+                &[
+                    b"metadata",
+                    &metadata_program_id.to_bytes(),
+                    &boost_mint.to_bytes(),
+                ],
+                &metadata_program_id,
+            );
+            accounts.push(AccountMeta::new_readonly(
+                spl_associated_token_account::get_associated_token_address(
+                    &wallet_pubkey,
+                    &boost_mint,
+                ),
+                false,
+            ));
+            accounts.push(AccountMeta::new_readonly(boost_mint, false));
+            accounts.push(AccountMeta::new_readonly(boost_metadata, false));
+        }
+        // println!("{:#?}", accounts);
+        let instruction = if emergency {
+            StakeInstruction::EmergencyUnstake
+        } else {
+            StakeInstruction::Unstake
+        };
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &instruction,
+            accounts,
+        )];
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&vec![&wallet_keypair], recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("stake") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+        let payer_keypair = matches
+            .value_of("payer")
+            .map(|path| read_keypair_file(path).expect("Can't open payer file-wallet"));
+        let payer_pubkey = payer_keypair
+            .as_ref()
+            .map_or(wallet_pubkey, |keypair| keypair.pubkey());
+
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+        let staker_token_account =
+            spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
+        println!("Staker Token Account: {}", staker_token_account);
+        let vault_token_account =
+            spl_associated_token_account::get_associated_token_address(&vault, &mint_pk);
+        println!("Vault Token Account: {}", vault_token_account);
+        let raw_vault_data = client.get_account_data(&vault).unwrap();
+        let vault_data = unpack_account::<VaultData>(&raw_vault_data[..]);
+        // nft vaults open a new position keyed by a fresh mint on every stake
+        let position_mint_keypair = if vault_data.position_nft != 0 {
+            Some(Keypair::new())
+        } else {
+            None
+        };
+        let stake_seed = position_mint_keypair
+            .as_ref()
+            .map_or(wallet_pubkey, |keypair| keypair.pubkey());
+        let (stake_data, _) = Pubkey::find_program_address(&[&stake_seed.to_bytes()], &program_id);
+        let amount = spl_token::ui_amount_to_amount(
+            matches.value_of("amount").unwrap().parse::<f64>().unwrap(),
+            9,
+        );
+        println!("Amount: {}", amount);
+
+        let mut accounts = vec![
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new(payer_pubkey, true),
+            AccountMeta::new(stake_data, false),
+            AccountMeta::new(staker_token_account, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(vault_token_account, false),
+            AccountMeta::new_readonly(MINT.parse::<Pubkey>().unwrap(), false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(
+                "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+                    .parse::<Pubkey>()
+                    .unwrap(),
+                false,
+            ),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(
+                "SysvarRent111111111111111111111111111111111"
+                    .parse::<Pubkey>()
+                    .unwrap(),
+                false,
+            ),
+        ];
+        if let Some(keypair) = &position_mint_keypair {
+            let position_mint = keypair.pubkey();
+            let metadata_program_id = METADATA_PROGRAM_ID.parse::<Pubkey>().unwrap();
+            let (metadata, _) = Pubkey::find_program_address(
+                &[
+                    b"metadata",
+                    &metadata_program_id.to_bytes(),
+                    &position_mint.to_bytes(),
+                ],
+                &metadata_program_id,
+            );
+            accounts.push(AccountMeta::new(position_mint, true));
+            accounts.push(AccountMeta::new(
+                spl_associated_token_account::get_associated_token_address(
+                    &wallet_pubkey,
+                    &position_mint,
+                ),
+                false,
+            ));
+            accounts.push(AccountMeta::new(metadata, false));
+            accounts.push(AccountMeta::new_readonly(metadata_program_id, false));
+            println!("Position Mint: {}", position_mint);
+        }
+        if vault_data.receipt_mint != Pubkey::default() {
+            accounts.push(AccountMeta::new(vault_data.receipt_mint, false));
+            accounts.push(AccountMeta::new(
+                spl_associated_token_account::get_associated_token_address(
+                    &wallet_pubkey,
+                    &vault_data.receipt_mint,
+                ),
+                false,
+            ));
+        }
+        // the referrer is fixed when the position opens, existing positions keep theirs
+        let referrer = pubkey_of(matches, "referrer");
+        let position_referrer = match client.get_account_data(&stake_data) {
+            Ok(raw_stake_data) => unpack_account::<StakeData>(&raw_stake_data[..]).referrer,
+            Err(_) => referrer.unwrap_or_default(),
+        };
+        if position_referrer != Pubkey::default() {
+            let (referral, _) = Pubkey::find_program_address(
+                &[REFERRAL_SEED, &position_referrer.to_bytes()],
+                &program_id,
+            );
+            accounts.push(AccountMeta::new(referral, false));
+        }
+        if let Some(boost_mint) = pubkey_of(matches, "boost_nft") {
+            let metadata_program_id = METADATA_PROGRAM_ID.parse::<Pubkey>().unwrap();
+            let (boost_metadata, _) = Pubkey::find_program_address(
+                &[
+                    b"metadata",
+                    &metadata_program_id.to_bytes(),
+                    &boost_mint.to_bytes(),
+                ],
+                &metadata_program_id,
+            );
+            accounts.push(AccountMeta::new_readonly(
+                spl_associated_token_account::get_associated_token_address(
+                    &wallet_pubkey,
+                    &boost_mint,
+                ),
+                false,
+            ));
+            accounts.push(AccountMeta::new_readonly(boost_mint, false));
+            accounts.push(AccountMeta::new_readonly(boost_metadata, false));
+        }
+
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::Stake { amount, referrer },
+            accounts,
+        )];
+        let mut signers = vec![&wallet_keypair];
+        if let Some(keypair) = &payer_keypair {
+            signers.push(keypair);
+        }
+        if let Some(keypair) = &position_mint_keypair {
+            signers.push(keypair);
+        }
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&payer_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("transfer-position") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+        let payer_keypair = matches
+            .value_of("payer")
+            .map(|path| read_keypair_file(path).expect("Can't open payer file-wallet"));
+        let payer_pubkey = payer_keypair
+            .as_ref()
+            .map_or(wallet_pubkey, |keypair| keypair.pubkey());
+
+        let new_owner = pubkey_of(matches, "new_owner").unwrap();
+        let (stake_data, _) =
+            Pubkey::find_program_address(&[&wallet_pubkey.to_bytes()], &program_id);
+        let (new_stake_data, _) =
+            Pubkey::find_program_address(&[&new_owner.to_bytes()], &program_id);
+
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::TransferPosition,
+            vec![
+                AccountMeta::new(wallet_pubkey, true),
+                AccountMeta::new(payer_pubkey, true),
+                AccountMeta::new(stake_data, false),
+                AccountMeta::new_readonly(new_owner, false),
+                AccountMeta::new(new_stake_data, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(
+                    "SysvarRent111111111111111111111111111111111"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+            ],
+        )];
+        let mut signers = vec![&wallet_keypair];
+        if let Some(keypair) = &payer_keypair {
+            signers.push(keypair);
+        }
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&payer_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("position transferred to: {:?}", new_owner);
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("generate_vault_address") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+        let payer_keypair = matches
+            .value_of("payer")
+            .map(|path| read_keypair_file(path).expect("Can't open payer file-wallet"));
+        let payer_pubkey = payer_keypair
+            .as_ref()
+            .map_or(wallet_pubkey, |keypair| keypair.pubkey());
+
+        let min_period = matches
+            .value_of("min_period")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        let reward_period = matches
+            .value_of("reward_period")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        let rate = matches.value_of("rate").unwrap().parse::<u64>().unwrap();
+        let early_withdrawal_fee = matches
+            .value_of("early_withdrawal_fee")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        let position_nft = matches.is_present("position_nft");
+        let receipt_token = matches.is_present("receipt_token");
+        let share_accounting = matches.is_present("share_accounting");
+        let linear_accrual = matches.is_present("linear_accrual");
+        let lock_policy = matches.value_of("lock_policy").map_or(0, |policy| {
+            LOCK_POLICIES
+                .iter()
+                .position(|name| *name == policy)
+                .unwrap() as u8
+        });
+        let rollover_policy = matches.value_of("rollover_policy").map_or(0, |policy| {
+            ROLLOVER_POLICIES
+                .iter()
+                .position(|name| *name == policy)
+                .unwrap() as u8
+        });
+        let boost_collection = pubkey_of(matches, "boost_collection").unwrap_or_default();
+        let boost_bps = matches
+            .value_of("boost_bps")
+            .map_or(0, |bps| bps.parse::<u64>().unwrap());
+        let referral_bps = matches
+            .value_of("referral_bps")
+            .map_or(0, |bps| bps.parse::<u64>().unwrap());
+
+        let mint = MINT.parse::<Pubkey>().unwrap();
+        let (vault_pda, _) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+        let vault_token_address =
+            spl_associated_token_account::get_associated_token_address(&vault_pda, &mint);
+
+        let mut instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::GenerateVault {
+                min_period,
+                reward_period,
+                rate,
+                early_withdrawal_fee,
+                position_nft,
+                receipt_token,
+                share_accounting,
+                boost_collection,
+                boost_bps,
+                referral_bps,
+                linear_accrual,
+                lock_policy,
+                rollover_policy,
+            },
+            vec![
+                AccountMeta::new_readonly(wallet_pubkey, true),
+                AccountMeta::new(payer_pubkey, true),
+                AccountMeta::new(vault_pda, false),
+                AccountMeta::new(vault_token_address, false),
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(
+                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+                AccountMeta::new(system_program::id(), false),
+                AccountMeta::new_readonly(
+                    "SysvarRent111111111111111111111111111111111"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+            ],
+        )];
+        if receipt_token {
+            let (receipt_mint, _) = Pubkey::find_program_address(&[RECEIPT_SEED], &program_id);
+            instructions[0]
+                .accounts
+                .push(AccountMeta::new(receipt_mint, false));
+            println!("receipt mint: {:?}", receipt_mint);
+        }
+
+        let mut signers = vec![&wallet_keypair];
+        if let Some(keypair) = &payer_keypair {
+            signers.push(keypair);
+        }
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&payer_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("vault account generated: {:?}", vault_pda);
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("generate_nft_vault") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+        let payer_keypair = matches
+            .value_of("payer")
+            .map(|path| read_keypair_file(path).expect("Can't open payer file-wallet"));
+        let payer_pubkey = payer_keypair
+            .as_ref()
+            .map_or(wallet_pubkey, |keypair| keypair.pubkey());
+
+        let collection = pubkey_of(matches, "collection").unwrap();
+        let min_period = matches
+            .value_of("min_period")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        let reward_period = matches
+            .value_of("reward_period")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+        let rate = spl_token::ui_amount_to_amount(
+            matches.value_of("rate").unwrap().parse::<f64>().unwrap(),
+            9,
+        );
+        let early_withdrawal_fee = matches
+            .value_of("early_withdrawal_fee")
+            .unwrap()
+            .parse::<u64>()
+            .unwrap();
+
+        let (vault_pda, _) = Pubkey::find_program_address(&[NFT_VAULT_SEED], &program_id);
+        let reward_token_address =
+            spl_associated_token_account::get_associated_token_address(&vault_pda, &mint_pk);
+
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::GenerateNftVault {
+                collection,
+                min_period,
+                reward_period,
+                rate,
+                linear_accrual: matches.is_present("linear_accrual"),
+                early_withdrawal_fee,
+            },
+            vec![
+                AccountMeta::new_readonly(wallet_pubkey, true),
+                AccountMeta::new(payer_pubkey, true),
+                AccountMeta::new(vault_pda, false),
+                AccountMeta::new(reward_token_address, false),
+                AccountMeta::new_readonly(mint_pk, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(
+                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(
+                    "SysvarRent111111111111111111111111111111111"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+            ],
+        )];
+
+        let mut signers = vec![&wallet_keypair];
+        if let Some(keypair) = &payer_keypair {
+            signers.push(keypair);
+        }
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&payer_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("nft vault account generated: {:?}", vault_pda);
+        println!("reward token account: {:?}", reward_token_address);
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("stake-nft") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+        let payer_keypair = matches
+            .value_of("payer")
+            .map(|path| read_keypair_file(path).expect("Can't open payer file-wallet"));
+        let payer_pubkey = payer_keypair
+            .as_ref()
+            .map_or(wallet_pubkey, |keypair| keypair.pubkey());
+
+        let nft_mint = pubkey_of(matches, "mint").unwrap();
+        let (vault, _) = Pubkey::find_program_address(&[NFT_VAULT_SEED], &program_id);
+        let (stake_data, _) =
+            Pubkey::find_program_address(&[NFT_VAULT_SEED, &nft_mint.to_bytes()], &program_id);
+        let metadata_program_id = METADATA_PROGRAM_ID.parse::<Pubkey>().unwrap();
+        let (metadata, _) = Pubkey::find_program_address(
+            &[
+                b"metadata",
+                &metadata_program_id.to_bytes(),
+                &nft_mint.to_bytes(),
+            ],
+            &metadata_program_id,
+        );
+
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::StakeNft,
+            vec![
+                AccountMeta::new(wallet_pubkey, true),
+                AccountMeta::new(payer_pubkey, true),
+                AccountMeta::new(stake_data, false),
+                AccountMeta::new(
+                    spl_associated_token_account::get_associated_token_address(
+                        &wallet_pubkey,
+                        &nft_mint,
+                    ),
+                    false,
+                ),
+                AccountMeta::new(vault, false),
+                AccountMeta::new(
+                    spl_associated_token_account::get_associated_token_address(&vault, &nft_mint),
+                    false,
+                ),
+                AccountMeta::new_readonly(
+                    spl_associated_token_account::get_associated_token_address(&vault, &mint_pk),
+                    false,
+                ),
+                AccountMeta::new_readonly(nft_mint, false),
+                AccountMeta::new_readonly(metadata, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+                AccountMeta::new_readonly(
+                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(
+                    "SysvarRent111111111111111111111111111111111"
+                        .parse::<Pubkey>()
+                        .unwrap(),
+                    false,
+                ),
+            ],
+        )];
+        let mut signers = vec![&wallet_keypair];
+        if let Some(keypair) = &payer_keypair {
+            signers.push(keypair);
+        }
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&payer_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("nft staked: {:?}", nft_mint);
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("unstake-nft") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+
+        let nft_mint = pubkey_of(matches, "mint").unwrap();
+        let (vault, _) = Pubkey::find_program_address(&[NFT_VAULT_SEED], &program_id);
+        let (stake_data, _) =
+            Pubkey::find_program_address(&[NFT_VAULT_SEED, &nft_mint.to_bytes()], &program_id);
+        let staker_token_account =
+            spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
+
+        let mut instructions = vec![];
+        // rewards are paid in the stake token, make sure there is somewhere to receive them
+        if client.get_account(&staker_token_account).is_err() {
+            instructions.push(
+                spl_associated_token_account::create_associated_token_account(
+                    &wallet_pubkey,
+                    &wallet_pubkey,
+                    &mint_pk,
+                ),
+            );
+        }
+        instructions.push(Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::UnstakeNft,
+            vec![
+                AccountMeta::new(wallet_pubkey, true),
+                AccountMeta::new(stake_data, false),
+                AccountMeta::new(
+                    spl_associated_token_account::get_associated_token_address(
+                        &wallet_pubkey,
+                        &nft_mint,
+                    ),
+                    false,
                 ),
-        )
-        .subcommand(
-            SubCommand::with_name("vault").arg(
-                Arg::with_name("env")
-                    .short("e")
-                    .long("env")
-                    .required(false)
-                    .takes_value(true),
-            ),
-        )
-        .subcommand(
-            SubCommand::with_name("stake-data")
-                .arg(
-                    Arg::with_name("env")
-                        .short("e")
-                        .long("env")
-                        .required(false)
-                        .takes_value(true),
-                )
-                .arg(
-                    Arg::with_name("address")
-                        .short("p")
-                        .long("pubkey")
-                        .required(true)
-                        .takes_value(true)
-                        .index(1),
+                AccountMeta::new(staker_token_account, false),
+                AccountMeta::new(vault, false),
+                AccountMeta::new(
+                    spl_associated_token_account::get_associated_token_address(&vault, &nft_mint),
+                    false,
                 ),
-        )
-        .get_matches();
-
-    let program_id = PROGRAM_ID.parse::<Pubkey>().unwrap();
-    let mint_pk = MINT.parse::<Pubkey>().unwrap();
+                AccountMeta::new(
+                    spl_associated_token_account::get_associated_token_address(&vault, &mint_pk),
+                    false,
+                ),
+                AccountMeta::new_readonly(nft_mint, false),
+                AccountMeta::new_readonly(spl_token::id(), false),
+            ],
+        ));
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&vec![&wallet_keypair], recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("nft unstaked: {:?}", nft_mint);
+        println!("tx id: {:?}", id);
+    }
 
-    if let Some(matches) = matches.subcommand_matches("withdraw") {
+    if let Some(matches) = matches.subcommand_matches("claim-referral") {
         let url = match matches.value_of("env") {
             Some("dev") => "https://api.devnet.solana.com",
             _ => "https://api.mainnet-beta.solana.com",
@@ -238,29 +1725,75 @@ fn main() {
         let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
         let wallet_pubkey = wallet_keypair.pubkey();
 
-        let amount = spl_token::ui_amount_to_amount(
-            matches.value_of("amount").unwrap().parse::<u64>().unwrap() as f64,
-            9,
-        );
-        println!("{}", amount);
-
         let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
-        let admin_token_address =
+        let (referral, _) =
+            Pubkey::find_program_address(&[REFERRAL_SEED, &wallet_pubkey.to_bytes()], &program_id);
+        let referrer_token_account =
             spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
-        let vault_token_address =
+        let vault_token_account =
             spl_associated_token_account::get_associated_token_address(&vault, &mint_pk);
 
-        let instructions = vec![Instruction::new_with_borsh(
+        let raw_referral_data = client.get_account_data(&referral).unwrap();
+        let referral_data = unpack_account::<ReferralData>(&raw_referral_data[..]);
+        println!(
+            "Pending: {}",
+            spl_token::amount_to_ui_amount(referral_data.pending, 9)
+        );
+        println!(
+            "Total Earned: {}",
+            spl_token::amount_to_ui_amount(referral_data.total_earned, 9)
+        );
+
+        let mut instructions = vec![];
+        if client.get_account(&referrer_token_account).is_err() {
+            instructions.push(
+                spl_associated_token_account::create_associated_token_account(
+                    &wallet_pubkey,
+                    &wallet_pubkey,
+                    &mint_pk,
+                ),
+            );
+        }
+        instructions.push(Instruction::new_with_borsh(
             program_id,
-            &StakeInstruction::Withdraw { amount },
+            &StakeInstruction::ClaimReferral,
             vec![
                 AccountMeta::new(wallet_pubkey, true),
-                AccountMeta::new(admin_token_address, false),
-                AccountMeta::new_readonly(vault, false),
-                AccountMeta::new(vault_token_address, false),
+                AccountMeta::new(referral, false),
+                AccountMeta::new(referrer_token_account, false),
+                AccountMeta::new(vault, false),
+                AccountMeta::new(vault_token_account, false),
                 AccountMeta::new_readonly(mint_pk, false),
                 AccountMeta::new_readonly(spl_token::id(), false),
             ],
+        ));
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        tx.sign(&vec![&wallet_keypair], recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+        let address = pubkey_of(matches, "address").unwrap();
+
+        let instructions = vec![Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::MigrateAccount,
+            vec![
+                AccountMeta::new(wallet_pubkey, true),
+                AccountMeta::new(address, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
         )];
         let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
         let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
@@ -269,7 +1802,7 @@ fn main() {
         println!("tx id: {:?}", id);
     }
 
-    if let Some(matches) = matches.subcommand_matches("unstake") {
+    if let Some(matches) = matches.subcommand_matches("set-emergency") {
         let url = match matches.value_of("env") {
             Some("dev") => "https://api.devnet.solana.com",
             _ => "https://api.mainnet-beta.solana.com",
@@ -280,37 +1813,37 @@ fn main() {
         let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
         let wallet_pubkey = wallet_keypair.pubkey();
 
-        let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
-        let staker_token_account =
-            spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
-        let vault_token_account =
-            spl_associated_token_account::get_associated_token_address(&vault, &mint_pk);
-        let (stake_data, _) =
-            Pubkey::find_program_address(&[&wallet_pubkey.to_bytes()], &program_id);
+        let vault_seed: &[u8] = if matches.is_present("nft") {
+            NFT_VAULT_SEED
+        } else {
+            VAULT_SEED
+        };
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[vault_seed], &program_id);
 
-        let accounts = vec![
+        let cosigners = cosigner_keypairs(matches);
+        let mut accounts = vec![
             AccountMeta::new(wallet_pubkey, true),
-            AccountMeta::new(stake_data, false),
-            AccountMeta::new(staker_token_account, false),
             AccountMeta::new(vault, false),
-            AccountMeta::new(vault_token_account, false),
-            AccountMeta::new_readonly(mint_pk, false),
-            AccountMeta::new_readonly(spl_token::id(), false),
         ];
-        // println!("{:#?}", accounts);
+        accounts.extend(multisig_accounts(&client, &vault, &cosigners));
+
         let instructions = vec![Instruction::new_with_borsh(
             program_id,
-            &StakeInstruction::Unstake,
+            &StakeInstruction::SetEmergency {
+                enabled: !matches.is_present("off"),
+            },
             accounts,
         )];
         let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
         let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
-        tx.sign(&vec![&wallet_keypair], recent_blockhash);
+        let mut signers = vec![&wallet_keypair];
+        signers.extend(cosigners.iter());
+        tx.sign(&signers, recent_blockhash);
         let id = client.send_transaction(&tx).expect("Transaction failed.");
         println!("tx id: {:?}", id);
     }
 
-    if let Some(matches) = matches.subcommand_matches("stake") {
+    if let Some(matches) = matches.subcommand_matches("set-pause") {
         let url = match matches.value_of("env") {
             Some("dev") => "https://api.devnet.solana.com",
             _ => "https://api.mainnet-beta.solana.com",
@@ -321,55 +1854,46 @@ fn main() {
         let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
         let wallet_pubkey = wallet_keypair.pubkey();
 
-        let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
-        let staker_token_account =
-            spl_associated_token_account::get_associated_token_address(&wallet_pubkey, &mint_pk);
-        println!("Staker Token Account: {}", staker_token_account);
-        let vault_token_account =
-            spl_associated_token_account::get_associated_token_address(&vault, &mint_pk);
-        println!("Vault Token Account: {}", vault_token_account);
-        let (stake_data, _) =
-            Pubkey::find_program_address(&[&wallet_pubkey.to_bytes()], &program_id);
-        let amount = spl_token::ui_amount_to_amount(
-            matches.value_of("amount").unwrap().parse::<f64>().unwrap(),
-            9,
-        );
-        println!("Amount: {}", amount);
+        let vault_seed: &[u8] = if matches.is_present("nft") {
+            NFT_VAULT_SEED
+        } else {
+            VAULT_SEED
+        };
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[vault_seed], &program_id);
+
+        // the flags given are paused, everything else is resumed
+        let paused = [
+            ("stake", PAUSE_STAKE),
+            ("claim", PAUSE_CLAIM),
+            ("unstake", PAUSE_UNSTAKE),
+            ("withdraw", PAUSE_WITHDRAW),
+        ]
+        .iter()
+        .filter(|(flag, _)| matches.is_present(flag))
+        .fold(0, |paused, (_, bit)| paused | bit);
+
+        let cosigners = cosigner_keypairs(matches);
+        let mut accounts = vec![
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new(vault, false),
+        ];
+        accounts.extend(multisig_accounts(&client, &vault, &cosigners));
 
         let instructions = vec![Instruction::new_with_borsh(
             program_id,
-            &StakeInstruction::Stake { amount },
-            vec![
-                AccountMeta::new(wallet_pubkey, true),
-                AccountMeta::new(stake_data, false),
-                AccountMeta::new(staker_token_account, false),
-                AccountMeta::new(vault, false),
-                AccountMeta::new(vault_token_account, false),
-                AccountMeta::new_readonly(MINT.parse::<Pubkey>().unwrap(), false),
-                AccountMeta::new_readonly(spl_token::id(), false),
-                AccountMeta::new_readonly(
-                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
-                        .parse::<Pubkey>()
-                        .unwrap(),
-                    false,
-                ),
-                AccountMeta::new_readonly(system_program::id(), false),
-                AccountMeta::new_readonly(
-                    "SysvarRent111111111111111111111111111111111"
-                        .parse::<Pubkey>()
-                        .unwrap(),
-                    false,
-                ),
-            ],
+            &StakeInstruction::SetPause { paused },
+            accounts,
         )];
         let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
         let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
-        tx.sign(&vec![&wallet_keypair], recent_blockhash);
+        let mut signers = vec![&wallet_keypair];
+        signers.extend(cosigners.iter());
+        tx.sign(&signers, recent_blockhash);
         let id = client.send_transaction(&tx).expect("Transaction failed.");
         println!("tx id: {:?}", id);
     }
 
-    if let Some(matches) = matches.subcommand_matches("generate_vault_address") {
+    if let Some(matches) = matches.subcommand_matches("set-multisig") {
         let url = match matches.value_of("env") {
             Some("dev") => "https://api.devnet.solana.com",
             _ => "https://api.mainnet-beta.solana.com",
@@ -380,63 +1904,111 @@ fn main() {
         let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
         let wallet_pubkey = wallet_keypair.pubkey();
 
-        let min_period = matches
-            .value_of("min_period")
-            .unwrap()
-            .parse::<u64>()
-            .unwrap();
-        let reward_period = matches
-            .value_of("reward_period")
-            .unwrap()
-            .parse::<u64>()
-            .unwrap();
-        let rate = matches.value_of("rate").unwrap().parse::<u64>().unwrap();
-        let early_withdrawal_fee = matches
-            .value_of("early_withdrawal_fee")
-            .unwrap()
-            .parse::<u64>()
-            .unwrap();
+        let vault_seed: &[u8] = if matches.is_present("nft") {
+            NFT_VAULT_SEED
+        } else {
+            VAULT_SEED
+        };
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[vault_seed], &program_id);
+        let (multisig, _multisig_bump) =
+            Pubkey::find_program_address(&[MULTISIG_SEED, &vault.to_bytes()], &program_id);
 
-        let mint = MINT.parse::<Pubkey>().unwrap();
-        let (vault_pda, _) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
-        let vault_token_address =
-            spl_associated_token_account::get_associated_token_address(&vault_pda, &mint);
+        // no signers hands the vault back to the single admin key
+        let signers: Vec<Pubkey> = matches
+            .values_of("signer")
+            .map(|values| values.map(|value| value.parse().unwrap()).collect())
+            .unwrap_or_default();
+        let threshold = value_of::<u8>(matches, "threshold").unwrap_or(0);
+
+        // the current multisig approves the change, so it's passed explicitly
+        let cosigners = cosigner_keypairs(matches);
+        let mut accounts = vec![
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(multisig, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ];
+        accounts.extend(
+            cosigners
+                .iter()
+                .map(|cosigner| AccountMeta::new_readonly(cosigner.pubkey(), true)),
+        );
 
         let instructions = vec![Instruction::new_with_borsh(
             program_id,
-            &StakeInstruction::GenerateVault {
-                min_period,
-                reward_period,
-                rate,
-                early_withdrawal_fee,
-            },
-            vec![
-                AccountMeta::new(wallet_pubkey, true),
-                AccountMeta::new(vault_pda, false),
-                AccountMeta::new(vault_token_address, false),
-                AccountMeta::new_readonly(mint, false),
-                AccountMeta::new_readonly(spl_token::id(), false),
-                AccountMeta::new_readonly(
-                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
-                        .parse::<Pubkey>()
-                        .unwrap(),
-                    false,
-                ),
-                AccountMeta::new(system_program::id(), false),
-                AccountMeta::new_readonly(
-                    "SysvarRent111111111111111111111111111111111"
-                        .parse::<Pubkey>()
-                        .unwrap(),
-                    false,
-                ),
-            ],
+            &StakeInstruction::SetMultisig { signers, threshold },
+            accounts,
         )];
+        let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
+        let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
+        let mut signers = vec![&wallet_keypair];
+        signers.extend(cosigners.iter());
+        tx.sign(&signers, recent_blockhash);
+        let id = client.send_transaction(&tx).expect("Transaction failed.");
+        println!("tx id: {:?}", id);
+    }
+
+    if let Some(matches) = matches.subcommand_matches("sweep") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let wallet_path = matches.value_of("sign").unwrap();
+        let wallet_keypair = read_keypair_file(wallet_path).expect("Can't open file-wallet");
+        let wallet_pubkey = wallet_keypair.pubkey();
+
+        let sweep_mint = pubkey_of(matches, "mint").unwrap();
+        let destination = pubkey_of(matches, "destination").unwrap_or(wallet_pubkey);
+
+        let raw_sweep_mint = client.get_account_data(&sweep_mint).unwrap();
+        let decimals = spl_token::state::Mint::unpack(&raw_sweep_mint[..])
+            .unwrap()
+            .decimals;
+        let amount = spl_token::ui_amount_to_amount(
+            matches.value_of("amount").unwrap().parse::<f64>().unwrap(),
+            decimals,
+        );
+
+        let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+        let source_token_account =
+            spl_associated_token_account::get_associated_token_address(&vault, &sweep_mint);
+        let destination_token_account =
+            spl_associated_token_account::get_associated_token_address(&destination, &sweep_mint);
 
+        let cosigners = cosigner_keypairs(matches);
+        let mut accounts = vec![
+            AccountMeta::new(wallet_pubkey, true),
+            AccountMeta::new_readonly(vault, false),
+            AccountMeta::new(source_token_account, false),
+            AccountMeta::new(destination_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ];
+        accounts.extend(multisig_accounts(&client, &vault, &cosigners));
+
+        let mut instructions = vec![];
+        if client.get_account(&destination_token_account).is_err() {
+            instructions.push(
+                spl_associated_token_account::create_associated_token_account(
+                    &wallet_pubkey,
+                    &destination,
+                    &sweep_mint,
+                ),
+            );
+        }
+        instructions.push(Instruction::new_with_borsh(
+            program_id,
+            &StakeInstruction::SweepToken { amount },
+            accounts,
+        ));
         let mut tx = Transaction::new_with_payer(&instructions, Some(&wallet_pubkey));
         let recent_blockhash = client.get_latest_blockhash().expect("Can't get blockhash");
-        tx.sign(&vec![&wallet_keypair], recent_blockhash);
+        let mut signers = vec![&wallet_keypair];
+        signers.extend(cosigners.iter());
+        tx.sign(&signers, recent_blockhash);
         let id = client.send_transaction(&tx).expect("Transaction failed.");
-        println!("vault account generated: {:?}", vault_pda);
         println!("tx id: {:?}", id);
     }
 
@@ -446,18 +2018,88 @@ fn main() {
             _ => "https://api.mainnet-beta.solana.com",
         };
         let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+        let vault_seed: &[u8] = if matches.is_present("nft") {
+            NFT_VAULT_SEED
+        } else {
+            VAULT_SEED
+        };
         let (vault_data_pk, _vault_data_bump) =
-            Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+            Pubkey::find_program_address(&[vault_seed], &program_id);
         println!("{:?}", vault_data_pk);
-        println!("{:?}", VAULT_SEED);
+        println!("{:?}", vault_seed);
         let raw_vault_data = client.get_account_data(&vault_data_pk).unwrap().clone();
-        let vault_data = VaultData::try_from_slice(&raw_vault_data[..]).unwrap();
+        let vault_data = unpack_account::<VaultData>(&raw_vault_data[..]);
 
         println!("Vault Mint: {}", vault_data.mint);
         println!("Minimum Staking Period: {}", vault_data.min_period);
         println!("Reward Period: {}", vault_data.reward_period);
-        println!("APR: {}", vault_data.rate);
-        println!("Early Withdrawal Fee: {}", vault_data.early_withdrawal_fee);
+        println!(
+            "Top-up Lock Policy: {}",
+            LOCK_POLICIES
+                .get(vault_data.lock_policy as usize)
+                .unwrap_or(&"unknown")
+        );
+        println!(
+            "Top-up Rollover Policy: {}",
+            ROLLOVER_POLICIES
+                .get(vault_data.rollover_policy as usize)
+                .unwrap_or(&"unknown")
+        );
+        if vault_data.linear_accrual != 0 {
+            println!("Accrual: linear, per second");
+        } else {
+            println!("Accrual: per whole reward period");
+        }
+        println!("APR: {}%", vault_data.rate as f64 / 100.0);
+        println!(
+            "Early Withdrawal Fee: {}%",
+            vault_data.early_withdrawal_fee as f64 / 100.0
+        );
+        println!("Position NFTs: {}", vault_data.position_nft != 0);
+        if vault_data.emergency != 0 {
+            println!("Emergency Mode: staking closed, principal-only exits open");
+        }
+        if vault_data.paused != 0 {
+            println!(
+                "Paused: stake {} claim {} unstake {} withdraw {}",
+                vault_data.paused & PAUSE_STAKE != 0,
+                vault_data.paused & PAUSE_CLAIM != 0,
+                vault_data.paused & PAUSE_UNSTAKE != 0,
+                vault_data.paused & PAUSE_WITHDRAW != 0
+            );
+        }
+        if vault_data.multisig != Pubkey::default() {
+            let raw_multisig_data = client.get_account_data(&vault_data.multisig).unwrap();
+            let multisig_data = unpack_account::<MultisigData>(&raw_multisig_data[..]);
+            println!(
+                "Admin Multisig: {} of {}",
+                multisig_data.threshold, multisig_data.signer_count
+            );
+            for signer in &multisig_data.signers[..multisig_data.signer_count as usize] {
+                println!("  {}", signer);
+            }
+        }
+        if vault_data.collection != Pubkey::default() {
+            println!("NFT Collection: {}", vault_data.collection);
+        }
+        if vault_data.boost_collection != Pubkey::default() {
+            println!("Boost Collection: {}", vault_data.boost_collection);
+            println!("Boost: {} bps", vault_data.boost_bps);
+        }
+        if vault_data.referral_bps != 0 {
+            println!("Referral Share: {} bps", vault_data.referral_bps);
+        }
+        if vault_data.receipt_mint != Pubkey::default() {
+            let raw_receipt_mint = client.get_account_data(&vault_data.receipt_mint).unwrap();
+            let receipt_supply = spl_token::state::Mint::unpack(&raw_receipt_mint[..])
+                .unwrap()
+                .supply;
+            println!("Receipt Mint: {}", vault_data.receipt_mint);
+            println!(
+                "Receipt Supply: {}",
+                spl_token::amount_to_ui_amount(receipt_supply, 9)
+            );
+        }
         println!(
             "Total Obligations: {}",
             spl_token::amount_to_ui_amount(vault_data.total_obligations, 9)
@@ -466,6 +2108,23 @@ fn main() {
             "Total Staked: {}",
             spl_token::amount_to_ui_amount(vault_data.total_staked, 9)
         );
+        if vault_data.share_accounting != 0 {
+            println!(
+                "Total Shares: {}",
+                spl_token::amount_to_ui_amount(vault_data.total_shares, 9)
+            );
+            println!(
+                "Pool Reserve: {}",
+                spl_token::amount_to_ui_amount(vault_data.pool_reserve, 9)
+            );
+            if vault_data.total_shares > 0 {
+                println!(
+                    "Share Price: {}",
+                    vault_data.total_staked as f64 / vault_data.total_shares as f64
+                );
+            }
+            println!("Last Accrual: {}", vault_data.last_accrual);
+        }
     }
 
     if let Some(matches) = matches.subcommand_matches("stake-data") {
@@ -486,13 +2145,17 @@ fn main() {
         );
 
         let raw_stake_data = client.get_account_data(&stake_data_pk).unwrap().clone();
-        let stake_data = StakeData::try_from_slice(&raw_stake_data[..]).unwrap();
+        let stake_data = unpack_account::<StakeData>(&raw_stake_data[..]);
 
-        println!("Started Staking: {}", stake_data.timestamp);
+        println!("Last Accrual: {}", stake_data.last_accrual);
+        if stake_data.unlock_at != 0 {
+            println!("Locked Since: {}", stake_data.lock_start);
+            println!("Unlocks: {}", stake_data.unlock_at);
+        }
         println!("Staker Address: {}", stake_data.staker);
         println!("Staker Info Account: {}", stake_data_pk);
         println!("Mint of Staked Token: {}", stake_data.mint);
-        println!("Staking Active: {}", stake_data.active);
+        println!("Staking Active: {}", stake_data.active != 0);
         println!(
             "Amount Withdrawn: {}",
             spl_token::amount_to_ui_amount(stake_data.withdrawn, 9)
@@ -509,5 +2172,126 @@ fn main() {
             "Maximum Reward: {}",
             spl_token::amount_to_ui_amount(stake_data.max_reward, 9)
         );
+        if stake_data.pending_reward != 0 {
+            println!(
+                "Pending Reward: {} (paid on unstake)",
+                spl_token::amount_to_ui_amount(stake_data.pending_reward, 9)
+            );
+        }
+        let (vault, _vault_bump) =
+            Pubkey::find_program_address(&[VAULT_SEED], &PROGRAM_ID.parse::<Pubkey>().unwrap());
+        let raw_vault_data = client.get_account_data(&vault).unwrap();
+        let vault_data = unpack_account::<VaultData>(&raw_vault_data[..]);
+        // positions opened before stakes recorded their rate settle at the vault rate
+        let max_reward = if stake_data.rate == 0 {
+            (stake_data.staked_amount as u128
+                * vault_data.rate as u128
+                * (10_000 + stake_data.boost_bps as u128)
+                / 10_000
+                / 10_000) as u64
+        } else {
+            stake_data.max_reward
+        };
+        if stake_data.rate == 0 && vault_data.share_accounting == 0 {
+            println!(
+                "Repriced Maximum Reward: {} (opened before rates were recorded)",
+                spl_token::amount_to_ui_amount(max_reward, 9)
+            );
+        }
+        if stake_data.active != 0 && vault_data.share_accounting == 0 {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            // accrual stops at maturity, a year after opening unless recorded otherwise
+            let maturity = if stake_data.maturity != 0 {
+                stake_data.maturity
+            } else {
+                stake_data.last_accrual + YEAR
+            };
+            let elapsed = now.min(maturity).saturating_sub(stake_data.last_accrual);
+            println!("Matures: {}", maturity);
+            println!(
+                "Accrued Reward: {} ({})",
+                spl_token::amount_to_ui_amount(
+                    estimate_reward(&vault_data, max_reward, YEAR, elapsed),
+                    9
+                ),
+                if vault_data.linear_accrual != 0 {
+                    "linear"
+                } else {
+                    "whole periods"
+                }
+            );
+        }
+        if stake_data.position_mint != Pubkey::default() {
+            println!("Position Mint: {}", stake_data.position_mint);
+        }
+        if stake_data.boost_bps != 0 {
+            println!("Boost: {} bps", stake_data.boost_bps);
+        }
+        if stake_data.referrer != Pubkey::default() {
+            println!("Referrer: {}", stake_data.referrer);
+        }
+        println!(
+            "Shares: {}",
+            spl_token::amount_to_ui_amount(stake_data.shares, 9)
+        );
+    }
+
+    if let Some(matches) = matches.subcommand_matches("stakes") {
+        let url = match matches.value_of("env") {
+            Some("dev") => "https://api.devnet.solana.com",
+            _ => "https://api.mainnet-beta.solana.com",
+        };
+        let client = RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed());
+
+        let mut filters = vec![
+            RpcFilterType::DataSize(size_of::<StakeData>() as u64),
+            RpcFilterType::Memcmp(Memcmp {
+                offset: 0,
+                bytes: MemcmpEncodedBytes::Bytes(vec![AccountKey::StakeV1 as u8]),
+                encoding: None,
+            }),
+        ];
+        if let Some(staker) = pubkey_of(matches, "staker") {
+            filters.push(RpcFilterType::Memcmp(Memcmp {
+                offset: STAKE_STAKER_OFFSET,
+                bytes: MemcmpEncodedBytes::Base58(staker.to_string()),
+                encoding: None,
+            }));
+        }
+        if matches.is_present("active") {
+            filters.push(RpcFilterType::Memcmp(Memcmp {
+                offset: STAKE_ACTIVE_OFFSET,
+                bytes: MemcmpEncodedBytes::Bytes(vec![1]),
+                encoding: None,
+            }));
+        }
+
+        let stakes = client
+            .get_program_accounts_with_config(
+                &program_id,
+                RpcProgramAccountsConfig {
+                    filters: Some(filters),
+                    account_config: RpcAccountInfoConfig {
+                        encoding: Some(UiAccountEncoding::Base64),
+                        ..RpcAccountInfoConfig::default()
+                    },
+                    with_context: None,
+                },
+            )
+            .unwrap();
+
+        for (stake_data_pk, account) in stakes {
+            let stake_data = unpack_account::<StakeData>(&account.data[..]);
+            println!(
+                "{} staker {} staked {} active {}",
+                stake_data_pk,
+                stake_data.staker,
+                spl_token::amount_to_ui_amount(stake_data.staked_amount, 9),
+                stake_data.active != 0
+            );
+        }
     }
 }
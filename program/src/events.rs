@@ -0,0 +1,319 @@
+// Structured events, logged with `sol_log_data` after every state change. Each `Program data:`
+// log line carries one Borsh-encoded `StakeEvent`; off-chain consumers base64-decode it and pass
+// the bytes to `StakeEvent::decode`. Variants and fields are append only, like instructions.
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{entrypoint::ProgramResult, log::sol_log_data, pubkey::Pubkey};
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct VaultCreated {
+    pub vault: Pubkey,
+    pub mint: Pubkey,
+    pub collection: Pubkey,
+    pub min_period: u64,
+    pub reward_period: u64,
+    pub rate: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct Staked {
+    pub staker: Pubkey,
+    pub stake: Pubkey,
+    pub amount: u64,
+    pub staked_amount: u64,
+    pub shares: u64,
+    pub referrer: Pubkey,
+    pub timestamp: u64,
+    pub vault_balance: u64,
+}
+
+// A reward settled without closing the position. `rollover_policy` says whether it was compounded
+// into the stake (0), paid to the staker (1) or left pending on the position (2).
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct RewardsClaimed {
+    pub staker: Pubkey,
+    pub stake: Pubkey,
+    pub reward: u64,
+    pub timestamp: u64,
+    pub rollover_policy: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct Unstaked {
+    pub staker: Pubkey,
+    pub stake: Pubkey,
+    pub principal: u64,
+    pub reward: u64,
+    pub withdrawn: u64,
+    pub timestamp: u64,
+    pub vault_balance: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct AdminWithdrew {
+    pub admin: Pubkey,
+    pub amount: u64,
+    pub timestamp: u64,
+    pub vault_balance: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct PositionTransferred {
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub stake: Pubkey,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct NftStaked {
+    pub staker: Pubkey,
+    pub nft_mint: Pubkey,
+    pub stake: Pubkey,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct NftUnstaked {
+    pub staker: Pubkey,
+    pub nft_mint: Pubkey,
+    pub stake: Pubkey,
+    pub reward: u64,
+    pub timestamp: u64,
+    pub vault_balance: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct ReferralCredited {
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub pending: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct ReferralClaimed {
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub timestamp: u64,
+    pub vault_balance: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+    pub key: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct TokenSwept {
+    pub admin: Pubkey,
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct EmergencySet {
+    pub admin: Pubkey,
+    pub enabled: bool,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct EmergencyUnstaked {
+    pub staker: Pubkey,
+    pub stake: Pubkey,
+    pub principal: u64,
+    pub timestamp: u64,
+    pub vault_balance: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct PauseSet {
+    pub admin: Pubkey,
+    pub vault: Pubkey,
+    pub paused: u8,
+    pub timestamp: u64,
+}
+
+// `multisig` is the default key when the vault went back to `ADMIN_PK`.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub struct MultisigSet {
+    pub admin: Pubkey,
+    pub vault: Pubkey,
+    pub multisig: Pubkey,
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize)]
+pub enum StakeEvent {
+    VaultCreated(VaultCreated),
+    Staked(Staked),
+    RewardsClaimed(RewardsClaimed),
+    Unstaked(Unstaked),
+    AdminWithdrew(AdminWithdrew),
+    PositionTransferred(PositionTransferred),
+    NftStaked(NftStaked),
+    NftUnstaked(NftUnstaked),
+    ReferralCredited(ReferralCredited),
+    ReferralClaimed(ReferralClaimed),
+    AccountMigrated(AccountMigrated),
+    TokenSwept(TokenSwept),
+    EmergencySet(EmergencySet),
+    EmergencyUnstaked(EmergencyUnstaked),
+    PauseSet(PauseSet),
+    MultisigSet(MultisigSet),
+}
+
+impl StakeEvent {
+    pub fn emit(&self) -> ProgramResult {
+        sol_log_data(&[&self.try_to_vec()?]);
+        Ok(())
+    }
+
+    // Decodes one `Program data:` payload, returning `None` for data this program didn't log.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        Self::try_from_slice(data).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> Vec<StakeEvent> {
+        let key = Pubkey::new_unique();
+        vec![
+            StakeEvent::VaultCreated(VaultCreated {
+                vault: key,
+                mint: Pubkey::new_unique(),
+                collection: Pubkey::default(),
+                min_period: 1,
+                reward_period: 2,
+                rate: 1_250,
+                timestamp: 3,
+            }),
+            StakeEvent::Staked(Staked {
+                staker: key,
+                stake: Pubkey::new_unique(),
+                amount: 10,
+                staked_amount: 20,
+                shares: 30,
+                referrer: Pubkey::new_unique(),
+                timestamp: 40,
+                vault_balance: 50,
+            }),
+            StakeEvent::RewardsClaimed(RewardsClaimed {
+                staker: key,
+                stake: Pubkey::new_unique(),
+                reward: 10,
+                timestamp: 20,
+                rollover_policy: 2,
+            }),
+            StakeEvent::Unstaked(Unstaked {
+                staker: key,
+                stake: Pubkey::new_unique(),
+                principal: 10,
+                reward: 20,
+                withdrawn: 30,
+                timestamp: 40,
+                vault_balance: 50,
+            }),
+            StakeEvent::AdminWithdrew(AdminWithdrew {
+                admin: key,
+                amount: 10,
+                timestamp: 20,
+                vault_balance: 30,
+            }),
+            StakeEvent::PositionTransferred(PositionTransferred {
+                from: key,
+                to: Pubkey::new_unique(),
+                stake: Pubkey::new_unique(),
+                timestamp: 10,
+            }),
+            StakeEvent::NftStaked(NftStaked {
+                staker: key,
+                nft_mint: Pubkey::new_unique(),
+                stake: Pubkey::new_unique(),
+                timestamp: 10,
+            }),
+            StakeEvent::NftUnstaked(NftUnstaked {
+                staker: key,
+                nft_mint: Pubkey::new_unique(),
+                stake: Pubkey::new_unique(),
+                reward: 10,
+                timestamp: 20,
+                vault_balance: 30,
+            }),
+            StakeEvent::ReferralCredited(ReferralCredited {
+                referrer: key,
+                amount: 10,
+                pending: 20,
+            }),
+            StakeEvent::ReferralClaimed(ReferralClaimed {
+                referrer: key,
+                amount: 10,
+                timestamp: 20,
+                vault_balance: 30,
+            }),
+            StakeEvent::AccountMigrated(AccountMigrated {
+                account: key,
+                key: 1,
+            }),
+            StakeEvent::TokenSwept(TokenSwept {
+                admin: key,
+                mint: Pubkey::new_unique(),
+                destination: Pubkey::new_unique(),
+                amount: 10,
+                timestamp: 20,
+            }),
+            StakeEvent::EmergencySet(EmergencySet {
+                admin: key,
+                enabled: true,
+                timestamp: 10,
+            }),
+            StakeEvent::EmergencyUnstaked(EmergencyUnstaked {
+                staker: key,
+                stake: Pubkey::new_unique(),
+                principal: 10,
+                timestamp: 20,
+                vault_balance: 30,
+            }),
+            StakeEvent::PauseSet(PauseSet {
+                admin: key,
+                vault: Pubkey::new_unique(),
+                paused: 5,
+                timestamp: 10,
+            }),
+            StakeEvent::MultisigSet(MultisigSet {
+                admin: key,
+                vault: Pubkey::new_unique(),
+                multisig: Pubkey::new_unique(),
+                signers: vec![Pubkey::new_unique(), Pubkey::new_unique()],
+                threshold: 2,
+                timestamp: 10,
+            }),
+        ]
+    }
+
+    #[test]
+    fn round_trip() {
+        for event in events() {
+            event.emit().unwrap();
+            let data = event.try_to_vec().unwrap();
+            assert_eq!(StakeEvent::decode(&data), Some(event));
+        }
+    }
+
+    #[test]
+    fn decode_rejects_foreign_data() {
+        let mut data = events()[0].try_to_vec().unwrap();
+        data.push(0);
+        assert_eq!(StakeEvent::decode(&data), None);
+        assert_eq!(StakeEvent::decode(&[16]), None);
+        assert_eq!(StakeEvent::decode(&[]), None);
+    }
+}
@@ -1,35 +1,88 @@
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use bytemuck::{Pod, Zeroable};
 use solana_program::borsh::try_from_slice_unchecked;
 use solana_program::program::{invoke, invoke_signed};
 use solana_program::{
     self,
     account_info::{next_account_info, AccountInfo},
-    declare_id, entrypoint,
+    declare_id,
     entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
     program_pack::Pack,
+    pubkey,
     pubkey::Pubkey,
-    system_instruction,
+    system_instruction, system_program,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use spl_associated_token_account;
-use spl_token;
+use std::mem::size_of;
+
+pub mod events;
+mod math;
+mod metadata;
+#[cfg(test)]
+mod tests;
+mod validation;
+
+use events::{
+    AccountMigrated, AdminWithdrew, EmergencySet, EmergencyUnstaked, MultisigSet, NftStaked,
+    NftUnstaked, PauseSet, PositionTransferred, ReferralClaimed, ReferralCredited, RewardsClaimed,
+    StakeEvent, Staked, TokenSwept, Unstaked, VaultCreated,
+};
+use math::{
+    accrued_reward, amount_for_shares, annual_reward, annualize, apply_boost, bps_of, deduct_fee,
+    mul_div, remove_boost, shares_for_amount, MathError,
+};
+use metadata::position_name;
+use validation::{
+    assert_admin, assert_pda, assert_program, assert_quorum, assert_solvent, assert_vault,
+    assert_writable, load_account, store_account,
+};
 
 const YEAR: u64 = 31_556_926;
 const VAULT_SEED: &[u8; 8] = b"___vault";
-const ADMIN_PK: &str = "";
-const MINT: &str = "";
-const DIVISOR: u64 = 20;
-const RATE: u64 = 2;
+const RECEIPT_SEED: &[u8; 8] = b"_receipt";
+const NFT_VAULT_SEED: &[u8; 8] = b"nftvault";
+const REFERRAL_SEED: &[u8; 8] = b"referral";
+const MULTISIG_SEED: &[u8; 8] = b"multisig";
+const ADMIN_PK: Pubkey = pubkey!("");
+const MINT: Pubkey = pubkey!("");
 const DECIMALS: u8 = 9;
-const STAKE_SIZE: u64 = 9 + 32 + 32 + 1 + 8 + 8 + 8 + 8; //105
-const VAULT_SIZE: u64 = 32 + 8 + 8 + 8 + 8 + 8 + 8; //90
+const STAKE_SIZE: u64 = size_of::<StakeData>() as u64; //256
+const VAULT_SIZE: u64 = size_of::<VaultData>() as u64; //280
+const REFERRAL_SIZE: u64 = size_of::<ReferralData>() as u64; //120
+const MULTISIG_SIZE: u64 = size_of::<MultisigData>() as u64; //424
+const MAX_SIGNERS: usize = 10;
+// unversioned stake accounts were allocated a byte past their 105-byte layout
+const LEGACY_STAKE_SIZE: u64 = 9 + 32 + 32 + 1 + 8 + 8 + 8 + 8; //106
+const LEGACY_VAULT_SIZE: u64 = 32 + 8 + 8 + 8 + 8 + 8 + 8; //80
+const BPS_DENOMINATOR: u64 = 10_000;
+// early-exit fee of vaults migrated from before the fee was read
+const DEFAULT_EARLY_EXIT_FEE_BPS: u64 = 500;
+// VaultData.paused bits, one per operation the admin can halt
+const PAUSE_STAKE: u8 = 1 << 0;
+const PAUSE_CLAIM: u8 = 1 << 1;
+const PAUSE_UNSTAKE: u8 = 1 << 2;
+const PAUSE_WITHDRAW: u8 = 1 << 3;
+// VaultData.lock_policy, how a top-up moves a position's lock. Extend is what vaults did before
+// the policy existed.
+const LOCK_EXTEND: u8 = 0;
+const LOCK_KEEP: u8 = 1;
+const LOCK_WEIGHTED: u8 = 2;
+// VaultData.rollover_policy, what a top-up does with the reward it settles. Compound is what
+// vaults did before the policy existed.
+const ROLLOVER_COMPOUND: u8 = 0;
+const ROLLOVER_PAYOUT: u8 = 1;
+const ROLLOVER_PENDING: u8 = 2;
+const POSITION_SYMBOL: &str = "SPOS";
+// JSON (image, description) shared by every position NFT, set at deployment like ADMIN_PK
+const POSITION_URI: &str = "";
 
 declare_id!("");
 
 // Declare and export the program's entrypoint
-entrypoint!(process_instruction);
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
 
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 enum StakeInstruction {
@@ -42,20 +95,178 @@ enum StakeInstruction {
         rate: u64,
         #[allow(dead_code)]
         early_withdrawal_fee: u64,
+        #[allow(dead_code)]
+        position_nft: bool,
+        #[allow(dead_code)]
+        receipt_token: bool,
+        #[allow(dead_code)]
+        share_accounting: bool,
+        #[allow(dead_code)]
+        boost_collection: Pubkey,
+        #[allow(dead_code)]
+        boost_bps: u64,
+        #[allow(dead_code)]
+        referral_bps: u64,
+        #[allow(dead_code)]
+        linear_accrual: bool,
+        #[allow(dead_code)]
+        lock_policy: u8,
+        #[allow(dead_code)]
+        rollover_policy: u8,
     },
     Stake {
         #[allow(dead_code)]
         amount: u64,
+        #[allow(dead_code)]
+        referrer: Option<Pubkey>,
     },
     Unstake,
     Withdraw {
         #[allow(dead_code)]
         amount: u64,
     },
+    TransferPosition,
+    GenerateNftVault {
+        #[allow(dead_code)]
+        collection: Pubkey,
+        #[allow(dead_code)]
+        min_period: u64,
+        #[allow(dead_code)]
+        reward_period: u64,
+        #[allow(dead_code)]
+        rate: u64,
+        #[allow(dead_code)]
+        linear_accrual: bool,
+        #[allow(dead_code)]
+        early_withdrawal_fee: u64,
+    },
+    StakeNft,
+    UnstakeNft,
+    ClaimReferral,
+    MigrateAccount,
+    SweepToken {
+        #[allow(dead_code)]
+        amount: u64,
+    },
+    SetEmergency {
+        #[allow(dead_code)]
+        enabled: bool,
+    },
+    EmergencyUnstake,
+    SetPause {
+        #[allow(dead_code)]
+        paused: u8,
+    },
+    SetMultisig {
+        #[allow(dead_code)]
+        signers: Vec<Pubkey>,
+        #[allow(dead_code)]
+        threshold: u8,
+    },
 }
 
-#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+// Leading byte of every program account, bumped whenever a layout changes.
+#[derive(Clone, Copy, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+enum AccountKey {
+    Uninitialized,
+    StakeV1,
+    VaultV1,
+    ReferralV1,
+    MultisigV1,
+}
+
+// Program state is stored as plain `repr(C)` structs and read with `bytemuck`, so loading an
+// account is a copy rather than a decode. Fields are ordered by alignment to leave no implicit
+// padding and every offset is fixed; flags are `u8` (0 or 1). New fields are carved out of
+// `reserved`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
 struct StakeData {
+    key: u8,
+    active: u8,
+    bump: u8,
+    padding: [u8; 5],
+    last_accrual: u64,
+    withdrawn: u64,
+    harvested: u64,
+    staked_amount: u64,
+    max_reward: u64,
+    shares: u64,
+    boost_bps: u64,
+    staker: Pubkey, //64
+    mint: Pubkey,
+    position_mint: Pubkey,
+    referrer: Pubkey,
+    rate: u64, //192
+    maturity: u64,
+    lock_start: u64,
+    unlock_at: u64,
+    pending_reward: u64,
+    reserved: [u8; 24],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+struct VaultData {
+    key: u8,
+    position_nft: u8,
+    share_accounting: u8,
+    bump: u8,
+    emergency: u8,
+    paused: u8,
+    linear_accrual: u8,
+    lock_policy: u8,
+    min_period: u64,
+    reward_period: u64,
+    rate: u64,
+    early_withdrawal_fee: u64,
+    total_obligations: u64,
+    total_staked: u64,
+    total_shares: u64,
+    last_accrual: u64,
+    boost_bps: u64,
+    referral_bps: u64,
+    mint: Pubkey, //88
+    receipt_mint: Pubkey,
+    collection: Pubkey,
+    boost_collection: Pubkey,
+    multisig: Pubkey,
+    rollover_policy: u8,
+    padding: [u8; 7],
+    pool_reserve: u64, //256
+    reserved: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+struct ReferralData {
+    key: u8,
+    bump: u8,
+    padding: [u8; 6],
+    pending: u64,
+    total_earned: u64,
+    referrer: Pubkey, //24
+    reserved: [u8; 64],
+}
+
+// Admin signers of a vault, at `[b"multisig", vault]`. Only the first `signer_count` entries of
+// `signers` are used.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Pod, Zeroable)]
+struct MultisigData {
+    key: u8,
+    threshold: u8,
+    signer_count: u8,
+    bump: u8,
+    padding: [u8; 4],
+    vault: Pubkey, //8
+    signers: [Pubkey; MAX_SIGNERS],
+    reserved: [u8; 64],
+}
+
+// Layouts written before accounts were versioned, kept to migrate them.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+struct LegacyStakeData {
     timestamp: u64,
     staker: Pubkey,
     mint: Pubkey,
@@ -67,7 +278,7 @@ struct StakeData {
 }
 
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
-struct VaultData {
+struct LegacyVaultData {
     mint: Pubkey,
     min_period: u64,
     reward_period: u64,
@@ -84,12 +295,142 @@ pub fn process_instruction(
     instruction_data: &[u8],
 ) -> ProgramResult {
     let accounts_iter = &mut accounts.iter();
-    let instruction: StakeInstruction = try_from_slice_unchecked(instruction_data).unwrap(); //DONT UNWRAP
+    // unknown tags and trailing bytes are both rejected
+    let instruction = StakeInstruction::try_from_slice(instruction_data)
+        .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    let admin = ADMIN_PK.parse::<Pubkey>().unwrap();
-    let mint = MINT.parse::<Pubkey>().unwrap();
+    let mint = MINT;
 
     match instruction {
+        StakeInstruction::TransferPosition => {
+            let owner = next_account_info(accounts_iter)?;
+            let payer = next_account_info(accounts_iter)?;
+            let stake_info = next_account_info(accounts_iter)?;
+            let new_owner = next_account_info(accounts_iter)?;
+            let new_stake_info = next_account_info(accounts_iter)?;
+            let sys_info = next_account_info(accounts_iter)?;
+            let rent_info = next_account_info(accounts_iter)?;
+
+            let rent = &Rent::from_account_info(rent_info)?;
+            let clock = Clock::get()?;
+
+            if !owner.is_signer {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x11));
+            }
+
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x14));
+            }
+
+            assert_writable(&[owner, payer, stake_info, new_stake_info])?;
+            assert_program(sys_info, &system_program::id())?;
+
+            if owner.key == new_owner.key {
+                //position already belongs to new_owner
+                return Err(ProgramError::Custom(0x111));
+            }
+
+            let mut stake_data: StakeData = load_account(stake_info, program_id)?;
+            assert_pda(
+                stake_info,
+                &[&owner.key.to_bytes()],
+                stake_data.bump,
+                program_id,
+                0x60,
+            )?;
+
+            if stake_data.active == 0 {
+                //staking is inactive
+                return Err(ProgramError::Custom(0x107));
+            }
+
+            if stake_data.staker != *owner.key {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x108));
+            }
+
+            if stake_data.position_mint != Pubkey::default() {
+                //nft positions move with the nft
+                return Err(ProgramError::Custom(0x112));
+            }
+
+            if new_stake_info.try_data_is_empty()? {
+                let (new_stake_address, new_stake_bump) =
+                    Pubkey::find_program_address(&[&new_owner.key.to_bytes()], program_id);
+
+                if new_stake_address != *new_stake_info.key {
+                    //wrong new_stake_info
+                    return Err(ProgramError::Custom(0x65));
+                }
+
+                msg!("No staking account found for new owner, creating...");
+                let size: u64 = STAKE_SIZE;
+                if new_stake_info.owner != program_id {
+                    let required_lamports = rent
+                        .minimum_balance(size as usize)
+                        .max(1)
+                        .saturating_sub(new_stake_info.lamports());
+                    invoke(
+                        &system_instruction::transfer(
+                            payer.key,
+                            &new_stake_address,
+                            required_lamports,
+                        ),
+                        &[payer.clone(), new_stake_info.clone(), sys_info.clone()],
+                    )?;
+                    invoke_signed(
+                        &system_instruction::allocate(&new_stake_address, size),
+                        &[new_stake_info.clone(), sys_info.clone()],
+                        &[&[&new_owner.key.to_bytes(), &[new_stake_bump]]],
+                    )?;
+
+                    invoke_signed(
+                        &system_instruction::assign(&new_stake_address, program_id),
+                        &[new_stake_info.clone(), sys_info.clone()],
+                        &[&[&new_owner.key.to_bytes(), &[new_stake_bump]]],
+                    )?;
+                }
+                stake_data.bump = new_stake_bump;
+            } else {
+                let new_stake_data: StakeData = load_account(new_stake_info, program_id)?;
+                assert_pda(
+                    new_stake_info,
+                    &[&new_owner.key.to_bytes()],
+                    new_stake_data.bump,
+                    program_id,
+                    0x65,
+                )?;
+
+                if new_stake_data.active != 0 {
+                    //new owner already holds an active position
+                    return Err(ProgramError::Custom(0x110));
+                }
+                stake_data.bump = new_stake_data.bump;
+            }
+
+            stake_data.staker = *new_owner.key;
+            store_account(new_stake_info, &stake_data)?;
+
+            // close the old position, returning its rent to the previous owner
+            let closed_lamports = stake_info.lamports();
+            **stake_info.lamports.borrow_mut() = 0;
+            **owner.lamports.borrow_mut() = match owner.lamports().checked_add(closed_lamports) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+            stake_info.data.borrow_mut().fill(0);
+
+            msg!("Position transferred to {:?}", new_owner.key);
+            StakeEvent::PositionTransferred(PositionTransferred {
+                from: *owner.key,
+                to: *new_owner.key,
+                stake: *new_stake_info.key,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
         StakeInstruction::Withdraw { amount } => {
             let admin_info = next_account_info(accounts_iter)?;
             let admin_token_account_info = next_account_info(accounts_iter)?;
@@ -100,44 +441,35 @@ pub fn process_instruction(
             let mint_info = next_account_info(accounts_iter)?;
             let token_program = next_account_info(accounts_iter)?;
 
-            if *admin_info.key != admin || !admin_info.is_signer {
-                //unauthorized access
-                return Err(ProgramError::Custom(0x231));
-            }
+            let clock = Clock::get()?;
 
             if *mint_info.key != mint {
                 return Err(ProgramError::Custom(0x67));
             }
 
-            let (vault_address, vault_bump) =
-                Pubkey::find_program_address(&[VAULT_SEED], &program_id);
             let admin_token_address =
                 spl_associated_token_account::get_associated_token_address(admin_info.key, &mint);
             let vault_token_address =
                 spl_associated_token_account::get_associated_token_address(vault_info.key, &mint);
 
-            let vault_data = VaultData::try_from_slice(&vault_info.data.borrow())?;
-            let vault_token_account_data = spl_token::state::Account::unpack_from_slice(
-                &vault_token_account_info.data.borrow(),
-            )?;
+            assert_writable(&[admin_token_account_info, vault_token_account_info])?;
 
-            // DONT UNWRAP
-            if amount
-                > vault_token_account_data
-                    .amount
-                    .checked_sub(
-                        (vault_data
-                            .total_obligations
-                            .checked_add(vault_data.total_staked))
-                        .unwrap(),
-                    )
-                    .unwrap()
-            {
-                return Err(ProgramError::InsufficientFunds);
-            }
+            // the surplus of either vault can be withdrawn
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            let vault_seed = assert_vault(vault_info, vault_data.bump, program_id, 0x261)?;
+            let vault_bump = vault_data.bump;
+
+            assert_admin(
+                program_id,
+                admin_info,
+                &vault_data.multisig,
+                accounts_iter,
+                0x231,
+            )?;
 
-            if vault_address != *vault_info.key {
-                return Err(ProgramError::Custom(0x261));
+            if vault_data.paused & PAUSE_WITHDRAW != 0 {
+                //withdrawals are paused
+                return Err(ProgramError::Custom(0x123));
             }
 
             if admin_token_address != *admin_token_account_info.key {
@@ -153,6 +485,17 @@ pub fn process_instruction(
                 return Err(ProgramError::Custom(0x345));
             }
 
+            // growth the pool has earned so far belongs to the stakers, not the surplus
+            if vault_data.share_accounting != 0 {
+                assert_writable(&[vault_info])?;
+                accrue_pool(
+                    &mut vault_data,
+                    clock.unix_timestamp as u64,
+                    token_balance(vault_token_account_info)?,
+                )?;
+                store_account(vault_info, &vault_data)?;
+            }
+
             invoke_signed(
                 &spl_token::instruction::transfer(
                     token_program.key,
@@ -168,8 +511,19 @@ pub fn process_instruction(
                     vault_info.clone(),
                     token_program.clone(),
                 ],
-                &[&[VAULT_SEED, &[vault_bump]]],
+                &[&[vault_seed, &[vault_bump]]],
             )?;
+
+            // only the surplus over stakers' funds and obligations can be withdrawn
+            assert_solvent(&vault_data, vault_token_account_info)?;
+
+            StakeEvent::AdminWithdrew(AdminWithdrew {
+                admin: *admin_info.key,
+                amount,
+                timestamp: clock.unix_timestamp as u64,
+                vault_balance: token_balance(vault_token_account_info)?,
+            })
+            .emit()?;
         }
         StakeInstruction::Unstake => {
             let staker = next_account_info(accounts_iter)?;
@@ -184,10 +538,6 @@ pub fn process_instruction(
 
             let clock = Clock::get()?;
 
-            let (stake_address, _stake_bump) =
-                Pubkey::find_program_address(&[&staker.key.to_bytes()], &program_id);
-            let (vault_address, vault_bump) =
-                Pubkey::find_program_address(&[VAULT_SEED], &program_id);
             let staker_token_account =
                 spl_associated_token_account::get_associated_token_address(staker.key, &mint);
             let vault_token_account =
@@ -198,21 +548,18 @@ pub fn process_instruction(
                 return Err(ProgramError::Custom(0x11));
             }
 
+            assert_writable(&[
+                stake_info,
+                staker_token_account_info,
+                vault_info,
+                vault_token_account_info,
+            ])?;
+
             if *token_program.key != spl_token::id() {
                 //wrong token_program
                 return Err(ProgramError::Custom(0x345));
             }
 
-            if stake_address != *stake_info.key {
-                //wrong stake_info
-                return Err(ProgramError::Custom(0x60));
-            }
-
-            if vault_address != *vault_info.key {
-                //wrong stake_info
-                return Err(ProgramError::Custom(0x61));
-            }
-
             if staker_token_account != *staker_token_account_info.key {
                 //wrong payer_token_account
                 return Err(ProgramError::Custom(0x62));
@@ -228,85 +575,227 @@ pub fn process_instruction(
                 return Err(ProgramError::Custom(0x67));
             }
 
-            let mut vault_data =
-                if let Ok(data) = VaultData::try_from_slice(&vault_info.data.borrow()) {
-                    data
-                } else {
-                    // can't deserialize vault data
-                    return Err(ProgramError::Custom(0x912));
-                };
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(vault_info, &[VAULT_SEED], vault_data.bump, program_id, 0x61)?;
+            let vault_bump = vault_data.bump;
 
-            let mut stake_data =
-                if let Ok(data) = StakeData::try_from_slice(&stake_info.data.borrow()) {
-                    data
-                } else {
-                    // can't deserialize stake data
-                    return Err(ProgramError::Custom(0x913));
-                };
+            if vault_data.paused & PAUSE_UNSTAKE != 0 {
+                //unstaking is paused
+                return Err(ProgramError::Custom(0x122));
+            }
+
+            // in nft mode the position is keyed by its nft and owned by whoever holds it
+            let position_accounts = if vault_data.position_nft != 0 {
+                let position_mint_info = next_account_info(accounts_iter)?;
+                let position_token_account_info = next_account_info(accounts_iter)?;
+                assert_writable(&[position_mint_info, position_token_account_info])?;
+                Some((position_mint_info, position_token_account_info))
+            } else {
+                None
+            };
+
+            let receipt_accounts = if vault_data.receipt_mint != Pubkey::default() {
+                let receipt_mint_info = next_account_info(accounts_iter)?;
+                let receipt_token_account_info = next_account_info(accounts_iter)?;
+                assert_writable(&[receipt_mint_info, receipt_token_account_info])?;
+                Some((receipt_mint_info, receipt_token_account_info))
+            } else {
+                None
+            };
+
+            // nft positions are keyed by their mint, the rest by the staker they were opened for
+            let mut stake_data: StakeData = load_account(stake_info, program_id)?;
+            let stake_seed = match position_accounts {
+                Some((position_mint_info, _)) => position_mint_info.key.to_bytes(),
+                None => stake_data.staker.to_bytes(),
+            };
+            assert_pda(
+                stake_info,
+                &[&stake_seed],
+                stake_data.bump,
+                program_id,
+                0x60,
+            )?;
 
-            if !stake_data.active {
+            if stake_data.active == 0 {
                 //staking is inactive
                 return Err(ProgramError::Custom(0x107));
             }
 
-            if stake_data.staker != *staker.key {
-                //unauthorized access
+            let referral_info = if stake_data.referrer != Pubkey::default() {
+                let referral_info = next_account_info(accounts_iter)?;
+                assert_writable(&[referral_info])?;
+                Some(referral_info)
+            } else {
+                None
+            };
+
+            let boost_accounts = if vault_data.boost_collection != Pubkey::default() {
+                if let Ok(boost_token_account_info) = next_account_info(accounts_iter) {
+                    let boost_mint_info = next_account_info(accounts_iter)?;
+                    let boost_metadata_info = next_account_info(accounts_iter)?;
+                    Some((
+                        boost_token_account_info,
+                        boost_mint_info,
+                        boost_metadata_info,
+                    ))
+                } else {
+                    None
+                }
+            } else {
+                None
+            };
+
+            if let Some((position_mint_info, position_token_account_info)) = position_accounts {
+                if stake_data.position_mint != *position_mint_info.key {
+                    //wrong position_mint_info
+                    return Err(ProgramError::Custom(0x68));
+                }
+
+                if position_token_account_info.owner != token_program.key {
+                    //wrong position_token_account_info
+                    return Err(ProgramError::Custom(0x69));
+                }
+
+                let position_token_account =
+                    spl_token::state::Account::unpack(&position_token_account_info.data.borrow())?;
+
+                if position_token_account.mint != *position_mint_info.key
+                    || position_token_account.owner != *staker.key
+                    || position_token_account.amount != 1
+                {
+                    //signer does not hold the position nft
+                    return Err(ProgramError::Custom(0x108));
+                }
+            } else if stake_data.staker != *staker.key
+                && (receipt_accounts.is_none() || stake_data.shares == 0)
+            {
+                //unauthorized access, unless redeeming with the position's receipt shares
                 return Err(ProgramError::Custom(0x108));
             }
 
+            // the boost recorded at stake time only pays if the nft is still held
+            let boosted = match boost_accounts {
+                Some((boost_token_account_info, boost_mint_info, boost_metadata_info)) => {
+                    verify_boost_holder(
+                        staker.key,
+                        boost_token_account_info,
+                        boost_mint_info,
+                        boost_metadata_info,
+                        &vault_data.boost_collection,
+                    )?;
+                    true
+                }
+                None => false,
+            };
+
             let elapsed_duration =
-                match (clock.unix_timestamp as u64).checked_sub(stake_data.timestamp) {
+                match (clock.unix_timestamp as u64).checked_sub(stake_data.last_accrual) {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-            let n_elapsed_rewards = match elapsed_duration.checked_div(vault_data.reward_period) {
-                Some(x) => x,
-                _ => return Err(ProgramError::Custom(0x109)),
-            };
+            let (reward, total_withdrawal) = if vault_data.share_accounting != 0 {
+                let vault_balance = spl_token::state::Account::unpack_from_slice(
+                    &vault_token_account_info.data.borrow(),
+                )?
+                .amount;
+                accrue_pool(&mut vault_data, clock.unix_timestamp as u64, vault_balance)?;
 
-            let reward_per_period = stake_data
-                .max_reward
-                .checked_div(YEAR.checked_div(vault_data.reward_period).unwrap())
-                .unwrap();
+                let assets = amount_for_shares(
+                    stake_data.shares,
+                    vault_data.total_shares,
+                    vault_data.total_staked,
+                )?;
 
-            let reward = match n_elapsed_rewards.checked_mul(reward_per_period) {
-                Some(x) => x,
-                _ => return Err(ProgramError::Custom(0x109)),
-            };
+                // the early-exit fee stays in the pool for the remaining stakers
+                let total_withdrawal =
+                    if is_locked(&stake_data, &vault_data, clock.unix_timestamp as u64) {
+                        deduct_fee(assets, vault_data.early_withdrawal_fee)?
+                    } else {
+                        assets
+                    };
 
-            let mut withdrawal_amount = match reward.checked_add(stake_data.staked_amount) {
-                Some(x) => x,
-                _ => return Err(ProgramError::Custom(0x109)),
-            };
+                msg!("shares redeemed {:?}", stake_data.shares);
+                msg!(
+                    "share value {:?}",
+                    spl_token::amount_to_ui_amount(assets, DECIMALS)
+                );
 
-            let total_withdrawal = if elapsed_duration < vault_data.min_period {
-                withdrawal_amount = match withdrawal_amount.checked_sub(
-                    match withdrawal_amount.checked_div(DIVISOR) {
-                        Some(x) => x,
-                        _ => return Err(ProgramError::Custom(0x109)),
-                    },
-                ) {
+                (
+                    assets.saturating_sub(stake_data.staked_amount),
+                    total_withdrawal,
+                )
+            } else {
+                reprice_legacy_position(&mut stake_data, &vault_data)?;
+
+                // max_reward is a year's worth, earned until the position matures
+                let mut reward = accrued_reward(
+                    stake_data.max_reward,
+                    YEAR,
+                    accrual_duration(&stake_data, clock.unix_timestamp as u64),
+                    vault_data.reward_period,
+                    vault_data.linear_accrual != 0,
+                )?;
+
+                if !boosted && stake_data.boost_bps != 0 {
+                    reward = remove_boost(reward, stake_data.boost_bps)?;
+                }
+
+                let withdrawal_amount = match reward.checked_add(stake_data.staked_amount) {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
-                withdrawal_amount
-            } else {
-                withdrawal_amount
+
+                // rewards left pending by earlier rollovers are paid out with the principal
+                let withdrawal_amount =
+                    match withdrawal_amount.checked_add(stake_data.pending_reward) {
+                        Some(x) => x,
+                        _ => return Err(ProgramError::Custom(0x109)),
+                    };
+
+                let total_withdrawal =
+                    if is_locked(&stake_data, &vault_data, clock.unix_timestamp as u64) {
+                        deduct_fee(withdrawal_amount, vault_data.early_withdrawal_fee)?
+                    } else {
+                        withdrawal_amount
+                    };
+
+                msg!("seconds elapsed {:?}", elapsed_duration);
+                msg!("reward {:?}", spl_token::amount_to_ui_amount(reward, 9));
+                //msg!("Already harvested {:?}", stake_data.harvested);
+                msg!(
+                    "max reward {:?}",
+                    spl_token::amount_to_ui_amount(stake_data.max_reward, 9)
+                );
+                msg!("already withdrawn {:?}", stake_data.withdrawn);
+                msg!(
+                    "final reward {:?}",
+                    spl_token::amount_to_ui_amount(reward, 9)
+                );
+
+                (reward, total_withdrawal)
             };
 
-            msg!("periods passed {:?}", n_elapsed_rewards);
-            msg!("reward {:?}", spl_token::amount_to_ui_amount(reward, 9));
-            //msg!("Already harvested {:?}", stake_data.harvested);
-            msg!(
-                "max reward {:?}",
-                spl_token::amount_to_ui_amount(stake_data.max_reward, 9)
-            );
-            msg!("already withdrawn {:?}", stake_data.withdrawn);
-            msg!(
-                "final reward {:?}",
-                spl_token::amount_to_ui_amount(reward, 9)
-            );
+            if let Some(referral_info) = referral_info {
+                let vault_balance = spl_token::state::Account::unpack_from_slice(
+                    &vault_token_account_info.data.borrow(),
+                )?
+                .amount;
+                credit_referrer(
+                    program_id,
+                    &mut vault_data,
+                    referral_info,
+                    &stake_data.referrer,
+                    reward,
+                    vault_balance,
+                )?;
+            }
+
+            if token_balance(vault_token_account_info)? < total_withdrawal {
+                //vault can't cover the withdrawal
+                return Err(ProgramError::Custom(0x115));
+            }
 
             invoke_signed(
                 &spl_token::instruction::transfer(
@@ -326,31 +815,116 @@ pub fn process_instruction(
                 &[&[VAULT_SEED, &[vault_bump]]],
             )?;
 
-            vault_data.total_obligations = match vault_data
-                .total_obligations
-                .checked_sub(stake_data.max_reward)
-            {
-                Some(x) => x,
-                _ => return Err(ProgramError::Custom(0x109)),
-            };
+            if let Some((position_mint_info, position_token_account_info)) = position_accounts {
+                invoke(
+                    &spl_token::instruction::burn(
+                        token_program.key,
+                        position_token_account_info.key,
+                        position_mint_info.key,
+                        staker.key,
+                        &[],
+                        1,
+                    )?,
+                    &[
+                        position_token_account_info.clone(),
+                        position_mint_info.clone(),
+                        staker.clone(),
+                        token_program.clone(),
+                    ],
+                )?;
+            }
 
-            vault_data.total_staked = match vault_data
-                .total_staked
-                .checked_sub(stake_data.staked_amount)
-            {
-                Some(x) => x,
-                _ => return Err(ProgramError::Custom(0x109)),
-            };
+            if let Some((receipt_mint_info, receipt_token_account_info)) = receipt_accounts {
+                if vault_data.receipt_mint != *receipt_mint_info.key {
+                    //wrong receipt_mint_info
+                    return Err(ProgramError::Custom(0x6a));
+                }
 
-            vault_data.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
+                // whoever holds the position's receipt shares redeems it by burning them
+                invoke(
+                    &spl_token::instruction::burn(
+                        token_program.key,
+                        receipt_token_account_info.key,
+                        receipt_mint_info.key,
+                        staker.key,
+                        &[],
+                        stake_data.shares,
+                    )?,
+                    &[
+                        receipt_token_account_info.clone(),
+                        receipt_mint_info.clone(),
+                        staker.clone(),
+                        token_program.clone(),
+                    ],
+                )?;
+            }
 
-            stake_data.active = false;
-            stake_data.harvested = match stake_data.harvested.checked_add(reward) {
-                Some(x) => x,
-                _ => return Err(ProgramError::Custom(0x109)),
-            };
+            if vault_data.share_accounting != 0 {
+                release_pool_reserve(&mut vault_data, stake_data.shares)?;
 
-            stake_data.withdrawn = match stake_data.withdrawn.checked_add(
+                vault_data.total_staked =
+                    match vault_data.total_staked.checked_sub(total_withdrawal) {
+                        Some(x) => x,
+                        _ => return Err(ProgramError::Custom(0x109)),
+                    };
+
+                vault_data.total_shares =
+                    match vault_data.total_shares.checked_sub(stake_data.shares) {
+                        Some(x) => x,
+                        _ => return Err(ProgramError::Custom(0x109)),
+                    };
+
+                // nobody is left to own retained fees, release them to the surplus
+                if vault_data.total_shares == 0 {
+                    vault_data.total_staked = 0;
+                }
+            } else {
+                vault_data.total_obligations = match vault_data
+                    .total_obligations
+                    .checked_sub(stake_data.max_reward)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                vault_data.total_obligations = match vault_data
+                    .total_obligations
+                    .checked_sub(stake_data.pending_reward)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                vault_data.total_staked = match vault_data
+                    .total_staked
+                    .checked_sub(stake_data.staked_amount)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+            }
+
+            store_account(vault_info, &vault_data)?;
+            assert_solvent(&vault_data, vault_token_account_info)?;
+
+            StakeEvent::Unstaked(Unstaked {
+                staker: *staker.key,
+                stake: *stake_info.key,
+                principal: stake_data.staked_amount,
+                reward,
+                withdrawn: total_withdrawal,
+                timestamp: clock.unix_timestamp as u64,
+                vault_balance: token_balance(vault_token_account_info)?,
+            })
+            .emit()?;
+
+            stake_data.active = 0;
+            stake_data.harvested = match stake_data.harvested.checked_add(reward) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+
+            stake_data.withdrawn = match stake_data.withdrawn.checked_add(
                 match reward.checked_add(stake_data.staked_amount) {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
@@ -359,13 +933,29 @@ pub fn process_instruction(
                 Some(x) => x,
                 _ => return Err(ProgramError::Custom(0x109)),
             };
+
+            // pending rewards are paid out here, so they count as harvested and withdrawn
+            stake_data.harvested = match stake_data.harvested.checked_add(stake_data.pending_reward)
+            {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+            stake_data.withdrawn = match stake_data.withdrawn.checked_add(stake_data.pending_reward)
+            {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
             stake_data.staked_amount = 0;
             stake_data.max_reward = 0;
-            stake_data.serialize(&mut &mut stake_info.data.borrow_mut()[..])?;
+            stake_data.shares = 0;
+            stake_data.boost_bps = 0;
+            stake_data.pending_reward = 0;
+            store_account(stake_info, &stake_data)?;
         }
 
-        StakeInstruction::Stake { amount } => {
+        StakeInstruction::Stake { amount, referrer } => {
             let staker = next_account_info(accounts_iter)?; //SOLANA WALLET
+            let payer = next_account_info(accounts_iter)?; //PAYS RENT, MAY BE A RELAYER
             let stake_data_info = next_account_info(accounts_iter)?; // COMPUTED BY SCY
             let staker_token_account_info = next_account_info(accounts_iter)?; // COMPUTED BY SPL-TOKEN
 
@@ -382,10 +972,6 @@ pub fn process_instruction(
             let clock = Clock::get()?;
             let rent = &Rent::from_account_info(rent_info)?;
 
-            let (stake_data, stake_data_bump) =
-                Pubkey::find_program_address(&[&staker.key.to_bytes()], &program_id);
-            let (vault_address, _vault_bump) =
-                Pubkey::find_program_address(&[VAULT_SEED], &program_id);
             let staker_token_address =
                 spl_associated_token_account::get_associated_token_address(staker.key, &mint);
             let vault_token_address =
@@ -401,16 +987,135 @@ pub fn process_instruction(
                 return Err(ProgramError::Custom(0x11));
             }
 
-            if stake_data != *stake_data_info.key {
-                //msg!("invalid stake_data account!");
-                return Err(ProgramError::Custom(0x10));
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x14));
+            }
+
+            assert_writable(&[
+                payer,
+                stake_data_info,
+                staker_token_account_info,
+                vault_info,
+                vault_token_account_info,
+            ])?;
+            assert_program(sys_info, &system_program::id())?;
+
+            let mut vault_config: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(
+                vault_info,
+                &[VAULT_SEED],
+                vault_config.bump,
+                program_id,
+                0x12,
+            )?;
+            let vault_bump = vault_config.bump;
+
+            if vault_config.emergency != 0 {
+                //staking is closed while the vault is in emergency mode
+                return Err(ProgramError::Custom(0x117));
+            }
+
+            if vault_config.paused & PAUSE_STAKE != 0 {
+                //staking is paused
+                return Err(ProgramError::Custom(0x120));
             }
 
-            if vault_address != *vault_info.key {
-                //incorrect vault_info
-                return Err(ProgramError::Custom(0x12));
+            // in nft mode every stake opens a new position keyed by a fresh mint
+            let position_accounts = if vault_config.position_nft != 0 {
+                let position_mint_info = next_account_info(accounts_iter)?;
+                let position_token_account_info = next_account_info(accounts_iter)?;
+                let metadata_info = next_account_info(accounts_iter)?;
+                let metadata_program = next_account_info(accounts_iter)?;
+                assert_writable(&[
+                    position_mint_info,
+                    position_token_account_info,
+                    metadata_info,
+                ])?;
+                Some((
+                    position_mint_info,
+                    position_token_account_info,
+                    metadata_info,
+                    metadata_program,
+                ))
+            } else {
+                None
+            };
+            let position_mint_info = position_accounts.map(|(info, _, _, _)| info);
+
+            let receipt_accounts = if vault_config.receipt_mint != Pubkey::default() {
+                let receipt_mint_info = next_account_info(accounts_iter)?;
+                let receipt_token_account_info = next_account_info(accounts_iter)?;
+                assert_writable(&[receipt_mint_info, receipt_token_account_info])?;
+                Some((receipt_mint_info, receipt_token_account_info))
+            } else {
+                None
+            };
+
+            let stake_seed = match position_mint_info {
+                Some(info) => info.key.to_bytes(),
+                None => staker.key.to_bytes(),
+            };
+
+            // existing positions carry their bump, only new ones pay for the search. The
+            // referrer is fixed when the position opens and credited on every settlement. The
+            // position is loaded once here and updated in place below
+            let (position_referrer, stake_data_bump, existing_stake) =
+                if stake_data_info.try_data_is_empty()? {
+                    let (stake_address, stake_bump) =
+                        Pubkey::find_program_address(&[&stake_seed], program_id);
+
+                    if stake_address != *stake_data_info.key {
+                        //msg!("invalid stake_data account!");
+                        return Err(ProgramError::Custom(0x10));
+                    }
+
+                    (referrer.unwrap_or_default(), stake_bump, None)
+                } else {
+                    let stake_data: StakeData = load_account(stake_data_info, program_id)?;
+                    assert_pda(
+                        stake_data_info,
+                        &[&stake_seed],
+                        stake_data.bump,
+                        program_id,
+                        0x10,
+                    )?;
+                    (stake_data.referrer, stake_data.bump, Some(stake_data))
+                };
+
+            if referrer == Some(*staker.key) {
+                //can't refer yourself
+                return Err(ProgramError::Custom(0x76));
             }
 
+            let referral_info = if position_referrer != Pubkey::default() {
+                let referral_info = next_account_info(accounts_iter)?;
+                assert_writable(&[referral_info])?;
+                Some(referral_info)
+            } else {
+                None
+            };
+
+            // holders of the boost collection may append their nft to earn a higher rate
+            let boost_bps = if vault_config.boost_collection != Pubkey::default() {
+                if let Ok(boost_token_account_info) = next_account_info(accounts_iter) {
+                    let boost_mint_info = next_account_info(accounts_iter)?;
+                    let boost_metadata_info = next_account_info(accounts_iter)?;
+                    verify_boost_holder(
+                        staker.key,
+                        boost_token_account_info,
+                        boost_mint_info,
+                        boost_metadata_info,
+                        &vault_config.boost_collection,
+                    )?;
+                    vault_config.boost_bps
+                } else {
+                    0
+                }
+            } else {
+                0
+            };
+
             if staker_token_address != *staker_token_account_info.key {
                 return Err(ProgramError::Custom(0x62));
             }
@@ -423,10 +1128,51 @@ pub fn process_instruction(
                 return Err(ProgramError::Custom(0x64));
             }
 
+            if let Some((receipt_mint_info, receipt_token_account_info)) = receipt_accounts {
+                if vault_config.receipt_mint != *receipt_mint_info.key {
+                    //wrong receipt_mint_info
+                    return Err(ProgramError::Custom(0x6a));
+                }
+
+                if spl_associated_token_account::get_associated_token_address(
+                    staker.key,
+                    receipt_mint_info.key,
+                ) != *receipt_token_account_info.key
+                {
+                    //wrong receipt_token_account_info
+                    return Err(ProgramError::Custom(0x6b));
+                }
+            }
+
+            let vault_balance = spl_token::state::Account::unpack_from_slice(
+                &vault_token_account_info.data.borrow(),
+            )?
+            .amount;
+
+            if vault_config.share_accounting != 0 {
+                accrue_pool(
+                    &mut vault_config,
+                    clock.unix_timestamp as u64,
+                    vault_balance,
+                )?;
+            }
+
+            // shares are priced against the pool before this deposit lands
+            let shares = if vault_config.share_accounting != 0 {
+                shares_for_amount(amount, vault_config.total_shares, vault_config.total_staked)?
+            } else if let Some((receipt_mint_info, _)) = receipt_accounts {
+                let receipt_supply =
+                    spl_token::state::Mint::unpack(&receipt_mint_info.data.borrow())?.supply;
+                shares_for_amount(amount, receipt_supply, vault_config.total_staked)?
+            } else {
+                0
+            };
+
             msg!("Stake Safety Checks OK.");
 
             //CHECK THESE ARE NOT WRONG
-            if stake_data_info.try_data_is_empty()? {
+            let new_position = existing_stake.is_none();
+            if new_position {
                 msg!("No staking account found, creating...");
                 let size: u64 = STAKE_SIZE;
                 if stake_data_info.owner != program_id {
@@ -435,158 +1181,284 @@ pub fn process_instruction(
                         .max(1)
                         .saturating_sub(stake_data_info.lamports());
                     invoke(
-                        &system_instruction::transfer(staker.key, &stake_data, required_lamports),
-                        &[staker.clone(), stake_data_info.clone(), sys_info.clone()],
+                        &system_instruction::transfer(
+                            payer.key,
+                            stake_data_info.key,
+                            required_lamports,
+                        ),
+                        &[payer.clone(), stake_data_info.clone(), sys_info.clone()],
                     )?;
                     invoke_signed(
-                        &system_instruction::allocate(&stake_data, size),
+                        &system_instruction::allocate(stake_data_info.key, size),
                         &[stake_data_info.clone(), sys_info.clone()],
-                        &[&[&staker.key.to_bytes(), &[stake_data_bump]]],
+                        &[&[&stake_seed, &[stake_data_bump]]],
                     )?;
 
                     invoke_signed(
-                        &system_instruction::assign(&stake_data, program_id),
+                        &system_instruction::assign(stake_data_info.key, program_id),
                         &[stake_data_info.clone(), sys_info.clone()],
-                        &[&[&staker.key.to_bytes(), &[stake_data_bump]]],
+                        &[&[&stake_seed, &[stake_data_bump]]],
                     )?;
                 }
-                let stake_data = StakeData::try_from_slice(&stake_data_info.data.borrow());
-                let mut vault_data =
-                    if let Ok(data) = VaultData::try_from_slice(&vault_info.data.borrow()) {
-                        data
-                    } else {
-                        return Err(ProgramError::InvalidAccountData);
-                    };
+            } else if position_mint_info.is_some() {
+                //position mints are single use
+                return Err(ProgramError::Custom(0x113));
+            }
 
-                let total_staker_reward = amount.checked_mul(vault_data.rate).unwrap();
+            if let Some(referral_info) = referral_info {
+                if referral_info.owner != program_id {
+                    let (referral_address, referral_bump) = Pubkey::find_program_address(
+                        &[REFERRAL_SEED, &position_referrer.to_bytes()],
+                        program_id,
+                    );
 
-                let harvested = if let Ok(data) = &stake_data {
-                    data.harvested
-                } else {
-                    0
-                };
+                    if referral_address != *referral_info.key {
+                        //wrong referral_info
+                        return Err(ProgramError::Custom(0x75));
+                    }
+
+                    msg!("No referral account found, creating...");
+                    let size: u64 = REFERRAL_SIZE;
+                    let required_lamports = rent
+                        .minimum_balance(size as usize)
+                        .max(1)
+                        .saturating_sub(referral_info.lamports());
+                    invoke(
+                        &system_instruction::transfer(
+                            payer.key,
+                            &referral_address,
+                            required_lamports,
+                        ),
+                        &[payer.clone(), referral_info.clone(), sys_info.clone()],
+                    )?;
+                    invoke_signed(
+                        &system_instruction::allocate(&referral_address, size),
+                        &[referral_info.clone(), sys_info.clone()],
+                        &[&[
+                            REFERRAL_SEED,
+                            &position_referrer.to_bytes(),
+                            &[referral_bump],
+                        ]],
+                    )?;
+                    invoke_signed(
+                        &system_instruction::assign(&referral_address, program_id),
+                        &[referral_info.clone(), sys_info.clone()],
+                        &[&[
+                            REFERRAL_SEED,
+                            &position_referrer.to_bytes(),
+                            &[referral_bump],
+                        ]],
+                    )?;
 
-                let _staked_amount = if let Ok(data) = &stake_data {
-                    data.staked_amount
+                    let referral_data = ReferralData {
+                        key: AccountKey::ReferralV1 as u8,
+                        padding: [0; 6],
+                        referrer: position_referrer,
+                        pending: 0,
+                        total_earned: 0,
+                        bump: referral_bump,
+                        reserved: [0; 64],
+                    };
+                    store_account(referral_info, &referral_data)?;
                 } else {
-                    amount
+                    let referral_data: ReferralData = load_account(referral_info, program_id)?;
+                    assert_pda(
+                        referral_info,
+                        &[REFERRAL_SEED, &position_referrer.to_bytes()],
+                        referral_data.bump,
+                        program_id,
+                        0x75,
+                    )?;
+                }
+            }
+
+            // a rollover's reward paid straight to the staker under ROLLOVER_PAYOUT
+            let mut payout = 0;
+
+            let stake_data = if vault_config.share_accounting != 0 {
+                let mut stake_data = match existing_stake {
+                    Some(stake_data) => stake_data,
+                    None => StakeData {
+                        key: AccountKey::StakeV1 as u8,
+                        padding: [0; 5],
+                        last_accrual: clock.unix_timestamp as u64,
+                        staker: *staker.key,
+                        harvested: 0,
+                        active: 1,
+                        withdrawn: 0,
+                        mint,
+                        staked_amount: 0,
+                        max_reward: 0,
+                        position_mint: position_mint_info
+                            .map_or(Pubkey::default(), |info| *info.key),
+                        shares: 0,
+                        boost_bps: 0,
+                        referrer: position_referrer,
+                        bump: stake_data_bump,
+                        rate: vault_config.rate,
+                        maturity: 0,
+                        lock_start: clock.unix_timestamp as u64,
+                        unlock_at: unlock_time(
+                            clock.unix_timestamp as u64,
+                            vault_config.min_period,
+                        )?,
+                        pending_reward: 0,
+                        reserved: [0; 24],
+                    },
                 };
 
-                let total_staked = vault_data.total_staked.checked_add(amount).unwrap();
+                if stake_data.staker != *staker.key {
+                    //unauthorized access
+                    return Err(ProgramError::Custom(0x108));
+                }
 
-                let total_obligations = match vault_data
-                    .total_obligations
-                    .checked_add(total_staker_reward)
-                {
+                if !new_position {
+                    relock(
+                        &mut stake_data,
+                        &vault_config,
+                        amount,
+                        clock.unix_timestamp as u64,
+                    )?;
+                }
+
+                stake_data.active = 1;
+                stake_data.last_accrual = clock.unix_timestamp as u64;
+                stake_data.staked_amount = match stake_data.staked_amount.checked_add(amount) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+                stake_data.shares = match stake_data.shares.checked_add(shares) {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-                // TODO
-                if spl_token::state::Account::unpack_from_slice(
-                    &vault_token_account_info.data.borrow(),
-                )?
-                .amount
-                    < (match total_obligations.checked_add(total_staked) {
-                        Some(x) => x,
-                        _ => return Err(ProgramError::Custom(0x109)),
-                    })
-                {
-                    return Err(ProgramError::InsufficientFunds);
-                }
+                vault_config.total_staked = match vault_config.total_staked.checked_add(amount) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+                vault_config.total_shares = match vault_config.total_shares.checked_add(shares) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
 
-                let stake_struct = StakeData {
-                    timestamp: clock.unix_timestamp as u64,
-                    staker: *staker.key,
-                    harvested,
-                    active: true,
-                    withdrawn: 0,
-                    mint,
-                    staked_amount: amount,
-                    max_reward: match amount.checked_mul(RATE) {
+                // a year's growth on the deposit is reserved, as fixed positions reserve
+                // max_reward. It is kept apart from referral credits, which only a claim releases
+                let reserve = annual_reward(amount, vault_config.rate)?;
+                vault_config.pool_reserve = match vault_config.pool_reserve.checked_add(reserve) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+                vault_config.total_obligations =
+                    match vault_config.total_obligations.checked_add(reserve) {
                         Some(x) => x,
                         _ => return Err(ProgramError::Custom(0x109)),
-                    },
-                };
-
-                vault_data.total_staked = total_staked;
-                vault_data.total_obligations = total_obligations;
-
-                stake_struct.serialize(&mut &mut stake_data_info.data.borrow_mut()[..])?;
-                vault_data.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
-                msg!("Stake Data Initialized");
-
-                let (vault, _vault_bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
-                if vault != *vault_info.key {
-                    //msg!("Wrong vault");
-                    return Err(ProgramError::Custom(0x07));
-                }
-            } else {
-                msg!("Previous staking account found, rolling-over...");
-                let mut vault_data =
-                    if let Ok(data) = VaultData::try_from_slice(&vault_info.data.borrow()) {
-                        data
-                    } else {
-                        // can't deserialize vault data
-                        return Err(ProgramError::Custom(0x912));
                     };
 
-                let mut stake_data =
-                    if let Ok(data) = StakeData::try_from_slice(&stake_data_info.data.borrow()) {
-                        data
-                    } else {
-                        // can't deserialize stake data
-                        return Err(ProgramError::Custom(0x913));
-                    };
+                msg!("shares issued {:?}", shares);
+                stake_data
+            } else if let Some(mut stake_data) = existing_stake {
+                msg!("Previous staking account found, rolling-over...");
+                if vault_config.paused & PAUSE_CLAIM != 0 {
+                    //a rollover settles rewards, which are paused
+                    return Err(ProgramError::Custom(0x121));
+                }
 
                 if stake_data.staker != *staker.key {
                     //unauthorized access
                     return Err(ProgramError::Custom(0x108));
                 }
 
+                reprice_legacy_position(&mut stake_data, &vault_config)?;
+
                 let elapsed_duration =
-                    match (clock.unix_timestamp as u64).checked_sub(stake_data.timestamp) {
+                    match (clock.unix_timestamp as u64).checked_sub(stake_data.last_accrual) {
                         Some(x) => x,
                         _ => return Err(ProgramError::Custom(0x109)),
                     };
 
-                let n_elapsed_rewards = match elapsed_duration.checked_div(vault_data.reward_period)
+                // max_reward is a year's worth, earned until the position matures
+                let mut reward = accrued_reward(
+                    stake_data.max_reward,
+                    YEAR,
+                    accrual_duration(&stake_data, clock.unix_timestamp as u64),
+                    vault_config.reward_period,
+                    vault_config.linear_accrual != 0,
+                )?;
+
+                // a boost that can no longer be proven is not paid out
+                if boost_bps == 0 && stake_data.boost_bps != 0 {
+                    reward = remove_boost(reward, stake_data.boost_bps)?;
+                }
+
+                if let Some(referral_info) = referral_info {
+                    credit_referrer(
+                        program_id,
+                        &mut vault_config,
+                        referral_info,
+                        &stake_data.referrer,
+                        reward,
+                        vault_balance,
+                    )?;
+                }
+
+                vault_config.total_obligations = match vault_config
+                    .total_obligations
+                    .checked_sub(stake_data.max_reward)
                 {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-                let reward_per_period = match stake_data.max_reward.checked_div(
-                    match YEAR.checked_div(vault_data.reward_period) {
-                        Some(x) => x,
-                        _ => return Err(ProgramError::Custom(0x109)),
-                    },
-                ) {
-                    Some(x) => x,
-                    _ => return Err(ProgramError::Custom(0x109)),
-                };
+                let mut compounded = 0;
+                match vault_config.rollover_policy {
+                    ROLLOVER_PAYOUT => {
+                        payout = reward;
+                        stake_data.harvested = match stake_data.harvested.checked_add(reward) {
+                            Some(x) => x,
+                            _ => return Err(ProgramError::Custom(0x109)),
+                        };
+                        stake_data.withdrawn = match stake_data.withdrawn.checked_add(reward) {
+                            Some(x) => x,
+                            _ => return Err(ProgramError::Custom(0x109)),
+                        };
+                    }
+                    ROLLOVER_PENDING => {
+                        // stays reserved for the position until it unstakes
+                        stake_data.pending_reward =
+                            match stake_data.pending_reward.checked_add(reward) {
+                                Some(x) => x,
+                                _ => return Err(ProgramError::Custom(0x109)),
+                            };
+                        vault_config.total_obligations =
+                            match vault_config.total_obligations.checked_add(reward) {
+                                Some(x) => x,
+                                _ => return Err(ProgramError::Custom(0x109)),
+                            };
+                    }
+                    _ => compounded = reward,
+                }
 
-                let reward = match n_elapsed_rewards.checked_mul(reward_per_period) {
+                vault_config.total_staked = match vault_config.total_staked.checked_add(compounded)
+                {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-                vault_data.total_staked = match vault_data.total_staked.checked_add(reward) {
-                    Some(x) => x,
-                    _ => return Err(ProgramError::Custom(0x109)),
-                };
+                // the compounded reward isn't a new deposit, only `amount` weighs on the lock
+                relock(
+                    &mut stake_data,
+                    &vault_config,
+                    amount,
+                    clock.unix_timestamp as u64,
+                )?;
 
-                vault_data.total_obligations = match vault_data
-                    .total_obligations
-                    .checked_sub(stake_data.max_reward)
-                {
+                stake_data.active = 1;
+                stake_data.shares = match stake_data.shares.checked_add(shares) {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
-
-                stake_data.active = true;
                 stake_data.staked_amount = match stake_data.staked_amount.checked_add(match amount
-                    .checked_add(reward)
+                    .checked_add(compounded)
                 {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
@@ -595,19 +1467,22 @@ pub fn process_instruction(
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-                stake_data.max_reward = match stake_data.staked_amount.checked_mul(RATE) {
-                    Some(x) => x,
-                    _ => return Err(ProgramError::Custom(0x109)),
-                };
+                stake_data.max_reward = apply_boost(
+                    annual_reward(stake_data.staked_amount, vault_config.rate)?,
+                    boost_bps,
+                )?;
+                stake_data.rate = vault_config.rate;
+                stake_data.boost_bps = boost_bps;
 
-                stake_data.timestamp = clock.unix_timestamp as u64;
+                stake_data.last_accrual = clock.unix_timestamp as u64;
+                stake_data.maturity = position_maturity(stake_data.last_accrual)?;
 
-                vault_data.total_staked = match vault_data.total_staked.checked_add(amount) {
+                vault_config.total_staked = match vault_config.total_staked.checked_add(amount) {
                     Some(x) => x,
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-                vault_data.total_obligations = match vault_data
+                vault_config.total_obligations = match vault_config
                     .total_obligations
                     .checked_add(stake_data.max_reward)
                 {
@@ -615,22 +1490,7 @@ pub fn process_instruction(
                     _ => return Err(ProgramError::Custom(0x109)),
                 };
 
-                if spl_token::state::Account::unpack_from_slice(
-                    &vault_token_account_info.data.borrow(),
-                )?
-                .amount
-                    < (match vault_data
-                        .total_obligations
-                        .checked_add(vault_data.total_staked)
-                    {
-                        Some(x) => x,
-                        _ => return Err(ProgramError::Custom(0x109)),
-                    })
-                {
-                    return Err(ProgramError::InsufficientFunds);
-                }
-
-                msg!("periods passed {:?}", n_elapsed_rewards);
+                msg!("seconds elapsed {:?}", elapsed_duration);
                 msg!(
                     "reward {:?}",
                     spl_token::amount_to_ui_amount(reward, DECIMALS)
@@ -645,9 +1505,65 @@ pub fn process_instruction(
                     "final reward {:?}",
                     spl_token::amount_to_ui_amount(reward, DECIMALS)
                 );
-                stake_data.serialize(&mut &mut stake_data_info.data.borrow_mut()[..])?;
-                vault_data.serialize(&mut &mut vault_info.data.borrow_mut()[..])?;
-            }
+                StakeEvent::RewardsClaimed(RewardsClaimed {
+                    staker: *staker.key,
+                    stake: *stake_data_info.key,
+                    reward,
+                    timestamp: clock.unix_timestamp as u64,
+                    rollover_policy: vault_config.rollover_policy,
+                })
+                .emit()?;
+                stake_data
+            } else {
+                let total_staker_reward =
+                    apply_boost(annual_reward(amount, vault_config.rate)?, boost_bps)?;
+
+                let total_staked = match vault_config.total_staked.checked_add(amount) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                let total_obligations = match vault_config
+                    .total_obligations
+                    .checked_add(total_staker_reward)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                let stake_data = StakeData {
+                    key: AccountKey::StakeV1 as u8,
+                    padding: [0; 5],
+                    last_accrual: clock.unix_timestamp as u64,
+                    staker: *staker.key,
+                    harvested: 0,
+                    active: 1,
+                    withdrawn: 0,
+                    mint,
+                    staked_amount: amount,
+                    max_reward: total_staker_reward,
+                    position_mint: position_mint_info.map_or(Pubkey::default(), |info| *info.key),
+                    shares,
+                    boost_bps,
+                    referrer: position_referrer,
+                    bump: stake_data_bump,
+                    rate: vault_config.rate,
+                    maturity: position_maturity(clock.unix_timestamp as u64)?,
+                    lock_start: clock.unix_timestamp as u64,
+                    unlock_at: unlock_time(clock.unix_timestamp as u64, vault_config.min_period)?,
+                    pending_reward: 0,
+                    reserved: [0; 24],
+                };
+
+                vault_config.total_staked = total_staked;
+                vault_config.total_obligations = total_obligations;
+
+                msg!("Stake Data Initialized");
+                stake_data
+            };
+
+            store_account(stake_data_info, &stake_data)?;
+            store_account(vault_info, &vault_config)?;
 
             if &spl_associated_token_account::get_associated_token_address(
                 staker.key,
@@ -659,7 +1575,7 @@ pub fn process_instruction(
             }
 
             if &spl_associated_token_account::get_associated_token_address(
-                &vault_info.key,
+                vault_info.key,
                 mint_info.key,
             ) != vault_token_account_info.key
             {
@@ -670,12 +1586,12 @@ pub fn process_instruction(
             if vault_token_account_info.owner != token_program.key {
                 invoke(
                     &spl_associated_token_account::create_associated_token_account(
-                        staker.key,
+                        payer.key,
                         vault_info.key,
                         mint_info.key,
                     ),
                     &[
-                        staker.clone(),
+                        payer.clone(),
                         vault_token_account_info.clone(),
                         vault_info.clone(),
                         mint_info.clone(),
@@ -702,30 +1618,276 @@ pub fn process_instruction(
                     token_program.clone(),
                 ],
             )?;
-        }
-
-        StakeInstruction::GenerateVault {
-            min_period,
-            reward_period,
-            rate,
-            early_withdrawal_fee,
-        } => {
-            let payer = next_account_info(accounts_iter)?;
-            let pda = next_account_info(accounts_iter)?;
-            let vault_token_account_info = next_account_info(accounts_iter)?;
-            let mint_info = next_account_info(accounts_iter)?;
 
-            let token_program = next_account_info(accounts_iter)?;
-            let atoken_program = next_account_info(accounts_iter)?;
-            let system_program = next_account_info(accounts_iter)?;
-            let rent_info = next_account_info(accounts_iter)?;
+            if payout != 0 {
+                invoke_signed(
+                    &spl_token::instruction::transfer(
+                        token_program.key,
+                        vault_token_account_info.key,
+                        staker_token_account_info.key,
+                        vault_info.key,
+                        &[],
+                        payout,
+                    )?,
+                    &[
+                        vault_token_account_info.clone(),
+                        staker_token_account_info.clone(),
+                        vault_info.clone(),
+                        token_program.clone(),
+                    ],
+                    &[&[VAULT_SEED, &[vault_bump]]],
+                )?;
 
-            let rent = &Rent::from_account_info(rent_info)?;
+                msg!("Reward paid out {:?}", payout);
+            }
 
-            let (vault_pda, vault_bump_seed) =
-                Pubkey::find_program_address(&[VAULT_SEED], &program_id);
-            let vault_token_account =
-                spl_associated_token_account::get_associated_token_address(&pda.key, mint_info.key);
+            if let Some((
+                position_mint_info,
+                position_token_account_info,
+                metadata_info,
+                metadata_program,
+            )) = position_accounts
+            {
+                let position_token_address =
+                    spl_associated_token_account::get_associated_token_address(
+                        staker.key,
+                        position_mint_info.key,
+                    );
+                let (metadata_address, _metadata_bump) = Pubkey::find_program_address(
+                    &[
+                        spl_token_metadata::state::PREFIX.as_bytes(),
+                        &spl_token_metadata::id().to_bytes(),
+                        &position_mint_info.key.to_bytes(),
+                    ],
+                    &spl_token_metadata::id(),
+                );
+
+                if !position_mint_info.is_signer {
+                    //position mint must be a fresh keypair
+                    return Err(ProgramError::Custom(0x15));
+                }
+
+                if position_token_address != *position_token_account_info.key {
+                    return Err(ProgramError::Custom(0x69));
+                }
+
+                if metadata_address != *metadata_info.key {
+                    return Err(ProgramError::Custom(0x66));
+                }
+
+                if *metadata_program.key != spl_token_metadata::id() {
+                    return Err(ProgramError::IncorrectProgramId);
+                }
+
+                let unlock =
+                    match (clock.unix_timestamp as u64).checked_add(vault_config.min_period) {
+                        Some(x) => x,
+                        _ => return Err(ProgramError::Custom(0x109)),
+                    };
+                let decimals = spl_token::state::Mint::unpack(&mint_info.data.borrow())?.decimals;
+
+                invoke(
+                    &system_instruction::create_account(
+                        payer.key,
+                        position_mint_info.key,
+                        rent.minimum_balance(spl_token::state::Mint::LEN),
+                        spl_token::state::Mint::LEN as u64,
+                        token_program.key,
+                    ),
+                    &[payer.clone(), position_mint_info.clone(), sys_info.clone()],
+                )?;
+
+                invoke(
+                    &spl_token::instruction::initialize_mint(
+                        token_program.key,
+                        position_mint_info.key,
+                        vault_info.key,
+                        None,
+                        0,
+                    )?,
+                    &[
+                        position_mint_info.clone(),
+                        rent_info.clone(),
+                        token_program.clone(),
+                    ],
+                )?;
+
+                invoke(
+                    &spl_associated_token_account::create_associated_token_account(
+                        payer.key,
+                        staker.key,
+                        position_mint_info.key,
+                    ),
+                    &[
+                        payer.clone(),
+                        position_token_account_info.clone(),
+                        staker.clone(),
+                        position_mint_info.clone(),
+                        sys_info.clone(),
+                        token_program.clone(),
+                        rent_info.clone(),
+                        token_assoc.clone(),
+                    ],
+                )?;
+
+                invoke_signed(
+                    &spl_token::instruction::mint_to(
+                        token_program.key,
+                        position_mint_info.key,
+                        position_token_account_info.key,
+                        vault_info.key,
+                        &[],
+                        1,
+                    )?,
+                    &[
+                        position_mint_info.clone(),
+                        position_token_account_info.clone(),
+                        vault_info.clone(),
+                        token_program.clone(),
+                    ],
+                    &[&[VAULT_SEED, &[vault_bump]]],
+                )?;
+
+                invoke_signed(
+                    &spl_token_metadata::instruction::create_metadata_accounts(
+                        *metadata_program.key,
+                        *metadata_info.key,
+                        *position_mint_info.key,
+                        *vault_info.key,
+                        *payer.key,
+                        *vault_info.key,
+                        position_name(amount, decimals, vault_config.rate, unlock),
+                        POSITION_SYMBOL.to_string(),
+                        POSITION_URI.to_string(),
+                        None,
+                        0,
+                        true,
+                        true,
+                    ),
+                    &[
+                        metadata_info.clone(),
+                        position_mint_info.clone(),
+                        vault_info.clone(),
+                        payer.clone(),
+                        vault_info.clone(),
+                        sys_info.clone(),
+                        rent_info.clone(),
+                        metadata_program.clone(),
+                    ],
+                    &[&[VAULT_SEED, &[vault_bump]]],
+                )?;
+
+                // fix the supply at one
+                invoke_signed(
+                    &spl_token::instruction::set_authority(
+                        token_program.key,
+                        position_mint_info.key,
+                        None,
+                        spl_token::instruction::AuthorityType::MintTokens,
+                        vault_info.key,
+                        &[],
+                    )?,
+                    &[
+                        position_mint_info.clone(),
+                        vault_info.clone(),
+                        token_program.clone(),
+                    ],
+                    &[&[VAULT_SEED, &[vault_bump]]],
+                )?;
+
+                msg!("Position NFT minted {:?}", position_mint_info.key);
+            }
+
+            if let Some((receipt_mint_info, receipt_token_account_info)) = receipt_accounts {
+                if receipt_token_account_info.owner != token_program.key {
+                    invoke(
+                        &spl_associated_token_account::create_associated_token_account(
+                            payer.key,
+                            staker.key,
+                            receipt_mint_info.key,
+                        ),
+                        &[
+                            payer.clone(),
+                            receipt_token_account_info.clone(),
+                            staker.clone(),
+                            receipt_mint_info.clone(),
+                            sys_info.clone(),
+                            token_program.clone(),
+                            rent_info.clone(),
+                            token_assoc.clone(),
+                        ],
+                    )?;
+                }
+
+                invoke_signed(
+                    &spl_token::instruction::mint_to(
+                        token_program.key,
+                        receipt_mint_info.key,
+                        receipt_token_account_info.key,
+                        vault_info.key,
+                        &[],
+                        shares,
+                    )?,
+                    &[
+                        receipt_mint_info.clone(),
+                        receipt_token_account_info.clone(),
+                        vault_info.clone(),
+                        token_program.clone(),
+                    ],
+                    &[&[VAULT_SEED, &[vault_bump]]],
+                )?;
+
+                msg!("Receipt shares minted {:?}", shares);
+            }
+
+            assert_solvent(&vault_config, vault_token_account_info)?;
+
+            StakeEvent::Staked(Staked {
+                staker: *staker.key,
+                stake: *stake_data_info.key,
+                amount,
+                staked_amount: stake_data.staked_amount,
+                shares,
+                referrer: position_referrer,
+                timestamp: clock.unix_timestamp as u64,
+                vault_balance: token_balance(vault_token_account_info)?,
+            })
+            .emit()?;
+        }
+
+        StakeInstruction::GenerateVault {
+            min_period,
+            reward_period,
+            rate,
+            early_withdrawal_fee,
+            position_nft,
+            receipt_token,
+            share_accounting,
+            boost_collection,
+            boost_bps,
+            referral_bps,
+            linear_accrual,
+            lock_policy,
+            rollover_policy,
+        } => {
+            let admin_info = next_account_info(accounts_iter)?;
+            let payer = next_account_info(accounts_iter)?;
+            let pda = next_account_info(accounts_iter)?;
+            let vault_token_account_info = next_account_info(accounts_iter)?;
+            let mint_info = next_account_info(accounts_iter)?;
+
+            let token_program = next_account_info(accounts_iter)?;
+            let atoken_program = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            let rent_info = next_account_info(accounts_iter)?;
+
+            let rent = &Rent::from_account_info(rent_info)?;
+            let clock = Clock::get()?;
+
+            let (vault_pda, vault_bump_seed) =
+                Pubkey::find_program_address(&[VAULT_SEED], program_id);
+            let vault_token_account =
+                spl_associated_token_account::get_associated_token_address(pda.key, mint_info.key);
 
             if pda.key != &vault_pda {
                 //msg!("Wrong account generated by client");
@@ -750,6 +1912,9 @@ pub fn process_instruction(
 
             // admin needs to be upgrade via bpf loader - upgrade authority
 
+            // regenerating a vault keeps its multisig, which must then approve it
+            let multisig = existing_multisig(pda, program_id);
+
             if pda.owner != program_id {
                 let size = VAULT_SIZE;
 
@@ -794,12 +1959,112 @@ pub fn process_instruction(
                 ],
             )?;
 
-            if *payer.key != admin || !payer.is_signer {
-                //unauthorized access
-                return Err(ProgramError::Custom(0x02));
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x03));
+            }
+
+            assert_writable(&[payer, pda, vault_token_account_info])?;
+            assert_program(system_program, &system_program::id())?;
+
+            if share_accounting && boost_collection != Pubkey::default() {
+                //pooled shares can't carry a per-position boost
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if reward_period == 0 && !linear_accrual {
+                //whole-period accrual needs a period to count
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if early_withdrawal_fee > BPS_DENOMINATOR {
+                //the fee can't exceed the withdrawal
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if boost_bps > BPS_DENOMINATOR {
+                //a boost can at most double the reward
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if referral_bps > BPS_DENOMINATOR {
+                //a referral credit can't exceed the reward it is taken from
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if lock_policy > LOCK_WEIGHTED {
+                //unknown lock policy
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if rollover_policy > ROLLOVER_PENDING {
+                //unknown rollover policy
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if share_accounting && rollover_policy != ROLLOVER_COMPOUND {
+                //pool growth compounds into every share, there is no per-position reward to move
+                return Err(ProgramError::InvalidArgument);
             }
 
+            // the vault is mint authority of the receipt mint, so shares can only
+            // be issued against deposits
+            let receipt_mint = if receipt_token {
+                let receipt_mint_info = next_account_info(accounts_iter)?;
+                assert_writable(&[receipt_mint_info])?;
+                let (receipt_mint, receipt_mint_bump) =
+                    Pubkey::find_program_address(&[RECEIPT_SEED], program_id);
+
+                if receipt_mint != *receipt_mint_info.key {
+                    //wrong receipt_mint_info
+                    return Err(ProgramError::Custom(0x6a));
+                }
+
+                if receipt_mint_info.owner != token_program.key {
+                    invoke_signed(
+                        &system_instruction::create_account(
+                            payer.key,
+                            &receipt_mint,
+                            rent.minimum_balance(spl_token::state::Mint::LEN),
+                            spl_token::state::Mint::LEN as u64,
+                            token_program.key,
+                        ),
+                        &[
+                            payer.clone(),
+                            receipt_mint_info.clone(),
+                            system_program.clone(),
+                        ],
+                        &[&[RECEIPT_SEED, &[receipt_mint_bump]]],
+                    )?;
+
+                    invoke(
+                        &spl_token::instruction::initialize_mint(
+                            token_program.key,
+                            &receipt_mint,
+                            &vault_pda,
+                            None,
+                            DECIMALS,
+                        )?,
+                        &[
+                            receipt_mint_info.clone(),
+                            rent_info.clone(),
+                            token_program.clone(),
+                        ],
+                    )?;
+                }
+                receipt_mint
+            } else {
+                Pubkey::default()
+            };
+
+            assert_admin(program_id, admin_info, &multisig, accounts_iter, 0x02)?;
+
             let contract_data = VaultData {
+                key: AccountKey::VaultV1 as u8,
+                emergency: 0,
+                paused: 0,
+                linear_accrual: linear_accrual as u8,
+                lock_policy,
                 mint: *mint_info.key,
                 min_period,
                 reward_period,
@@ -807,10 +2072,1763 @@ pub fn process_instruction(
                 early_withdrawal_fee,
                 total_obligations: 0,
                 total_staked: 0,
+                position_nft: position_nft as u8,
+                receipt_mint,
+                share_accounting: share_accounting as u8,
+                total_shares: 0,
+                last_accrual: clock.unix_timestamp as u64,
+                collection: Pubkey::default(),
+                boost_collection,
+                boost_bps,
+                referral_bps,
+                bump: vault_bump_seed,
+                multisig,
+                rollover_policy,
+                padding: [0; 7],
+                pool_reserve: 0,
+                reserved: [0; 16],
             };
-            contract_data.serialize(&mut &mut pda.data.borrow_mut()[..])?;
+            store_account(pda, &contract_data)?;
+
+            StakeEvent::VaultCreated(VaultCreated {
+                vault: *pda.key,
+                mint: *mint_info.key,
+                collection: Pubkey::default(),
+                min_period,
+                reward_period,
+                rate,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
         }
-    };
+
+        StakeInstruction::GenerateNftVault {
+            collection,
+            min_period,
+            reward_period,
+            rate,
+            linear_accrual,
+            early_withdrawal_fee,
+        } => {
+            let admin_info = next_account_info(accounts_iter)?;
+            let payer = next_account_info(accounts_iter)?;
+            let pda = next_account_info(accounts_iter)?;
+            let reward_token_account_info = next_account_info(accounts_iter)?;
+            let mint_info = next_account_info(accounts_iter)?;
+
+            let token_program = next_account_info(accounts_iter)?;
+            let atoken_program = next_account_info(accounts_iter)?;
+            let system_program = next_account_info(accounts_iter)?;
+            let rent_info = next_account_info(accounts_iter)?;
+
+            let rent = &Rent::from_account_info(rent_info)?;
+            let clock = Clock::get()?;
+
+            let (vault_pda, vault_bump_seed) =
+                Pubkey::find_program_address(&[NFT_VAULT_SEED], program_id);
+            let reward_token_account =
+                spl_associated_token_account::get_associated_token_address(&vault_pda, &mint);
+
+            // regenerating a vault keeps its multisig, which must then approve it
+            let multisig = existing_multisig(pda, program_id);
+            assert_admin(program_id, admin_info, &multisig, accounts_iter, 0x02)?;
+
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x03));
+            }
+
+            assert_writable(&[payer, pda, reward_token_account_info])?;
+            assert_program(system_program, &system_program::id())?;
+
+            if pda.key != &vault_pda {
+                //wrong nft vault
+                return Err(ProgramError::Custom(0x70));
+            }
+
+            if reward_period == 0 {
+                //rate is paid per period
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if early_withdrawal_fee > BPS_DENOMINATOR {
+                //the fee can't exceed the reward
+                return Err(ProgramError::InvalidArgument);
+            }
+
+            if reward_token_account != *reward_token_account_info.key {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            if *token_program.key != spl_token::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            if *atoken_program.key != spl_associated_token_account::id() {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            if *mint_info.key != mint {
+                return Err(ProgramError::Custom(0x00));
+            }
+
+            if pda.owner != program_id {
+                let size = VAULT_SIZE;
+
+                let required_lamports = rent
+                    .minimum_balance(size as usize)
+                    .max(1)
+                    .saturating_sub(pda.lamports());
+
+                invoke(
+                    &system_instruction::transfer(payer.key, &vault_pda, required_lamports),
+                    &[payer.clone(), pda.clone(), system_program.clone()],
+                )?;
+
+                invoke_signed(
+                    &system_instruction::allocate(&vault_pda, size),
+                    &[pda.clone(), system_program.clone()],
+                    &[&[NFT_VAULT_SEED, &[vault_bump_seed]]],
+                )?;
+
+                invoke_signed(
+                    &system_instruction::assign(&vault_pda, program_id),
+                    &[pda.clone(), system_program.clone()],
+                    &[&[NFT_VAULT_SEED, &[vault_bump_seed]]],
+                )?;
+            }
+
+            invoke(
+                &spl_associated_token_account::create_associated_token_account(
+                    payer.key,
+                    &vault_pda,
+                    mint_info.key,
+                ),
+                &[
+                    payer.clone(),
+                    reward_token_account_info.clone(),
+                    pda.clone(),
+                    mint_info.clone(),
+                    system_program.clone(),
+                    token_program.clone(),
+                    rent_info.clone(),
+                    atoken_program.clone(),
+                ],
+            )?;
+
+            // total_staked counts nfts, rate is the reward per nft per period. The early-exit fee
+            // comes out of the reward
+            let contract_data = VaultData {
+                key: AccountKey::VaultV1 as u8,
+                emergency: 0,
+                paused: 0,
+                linear_accrual: linear_accrual as u8,
+                lock_policy: LOCK_EXTEND,
+                mint: *mint_info.key,
+                min_period,
+                reward_period,
+                rate,
+                early_withdrawal_fee,
+                total_obligations: 0,
+                total_staked: 0,
+                position_nft: 0,
+                receipt_mint: Pubkey::default(),
+                share_accounting: 0,
+                total_shares: 0,
+                last_accrual: clock.unix_timestamp as u64,
+                collection,
+                boost_collection: Pubkey::default(),
+                boost_bps: 0,
+                referral_bps: 0,
+                bump: vault_bump_seed,
+                multisig,
+                rollover_policy: ROLLOVER_COMPOUND,
+                padding: [0; 7],
+                pool_reserve: 0,
+                reserved: [0; 16],
+            };
+            store_account(pda, &contract_data)?;
+
+            StakeEvent::VaultCreated(VaultCreated {
+                vault: *pda.key,
+                mint: *mint_info.key,
+                collection,
+                min_period,
+                reward_period,
+                rate,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
+
+        StakeInstruction::StakeNft => {
+            let staker = next_account_info(accounts_iter)?;
+            let payer = next_account_info(accounts_iter)?;
+            let stake_info = next_account_info(accounts_iter)?;
+            let staker_nft_account_info = next_account_info(accounts_iter)?;
+
+            let vault_info = next_account_info(accounts_iter)?;
+            let vault_nft_account_info = next_account_info(accounts_iter)?;
+            let reward_token_account_info = next_account_info(accounts_iter)?;
+
+            let nft_mint_info = next_account_info(accounts_iter)?;
+            let metadata_info = next_account_info(accounts_iter)?;
+
+            let token_program = next_account_info(accounts_iter)?;
+            let token_assoc = next_account_info(accounts_iter)?;
+            let sys_info = next_account_info(accounts_iter)?;
+            let rent_info = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+            let rent = &Rent::from_account_info(rent_info)?;
+
+            let staker_nft_account = spl_associated_token_account::get_associated_token_address(
+                staker.key,
+                nft_mint_info.key,
+            );
+            let vault_nft_account = spl_associated_token_account::get_associated_token_address(
+                vault_info.key,
+                nft_mint_info.key,
+            );
+            let reward_token_account =
+                spl_associated_token_account::get_associated_token_address(vault_info.key, &mint);
+
+            if *token_program.key != spl_token::id() {
+                //wrong token_program
+                return Err(ProgramError::Custom(0x345));
+            }
+
+            if !staker.is_signer {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x11));
+            }
+
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x14));
+            }
+
+            assert_writable(&[
+                payer,
+                stake_info,
+                staker_nft_account_info,
+                vault_info,
+                vault_nft_account_info,
+            ])?;
+            assert_program(sys_info, &system_program::id())?;
+
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(
+                vault_info,
+                &[NFT_VAULT_SEED],
+                vault_data.bump,
+                program_id,
+                0x70,
+            )?;
+
+            if vault_data.emergency != 0 {
+                //staking is closed while the vault is in emergency mode
+                return Err(ProgramError::Custom(0x117));
+            }
+
+            if vault_data.paused & PAUSE_STAKE != 0 {
+                //staking is paused
+                return Err(ProgramError::Custom(0x120));
+            }
+
+            if staker_nft_account != *staker_nft_account_info.key {
+                return Err(ProgramError::Custom(0x62));
+            }
+
+            if vault_nft_account != *vault_nft_account_info.key {
+                //wrong nft escrow
+                return Err(ProgramError::Custom(0x71));
+            }
+
+            if reward_token_account != *reward_token_account_info.key {
+                return Err(ProgramError::Custom(0x63));
+            }
+
+            verify_collection_nft(nft_mint_info, metadata_info, &vault_data.collection)?;
+
+            // reserve a year of rewards for this nft
+            let max_reward = annualize(vault_data.rate, vault_data.reward_period)?;
+
+            vault_data.total_obligations =
+                match vault_data.total_obligations.checked_add(max_reward) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+            vault_data.total_staked = match vault_data.total_staked.checked_add(1) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+
+            let stake_bump = if stake_info.try_data_is_empty()? {
+                let (stake_address, stake_bump) = Pubkey::find_program_address(
+                    &[NFT_VAULT_SEED, &nft_mint_info.key.to_bytes()],
+                    program_id,
+                );
+
+                if stake_address != *stake_info.key {
+                    //wrong stake_info
+                    return Err(ProgramError::Custom(0x10));
+                }
+
+                msg!("No nft staking account found, creating...");
+                let size: u64 = STAKE_SIZE;
+                if stake_info.owner != program_id {
+                    let required_lamports = rent
+                        .minimum_balance(size as usize)
+                        .max(1)
+                        .saturating_sub(stake_info.lamports());
+                    invoke(
+                        &system_instruction::transfer(payer.key, &stake_address, required_lamports),
+                        &[payer.clone(), stake_info.clone(), sys_info.clone()],
+                    )?;
+                    invoke_signed(
+                        &system_instruction::allocate(&stake_address, size),
+                        &[stake_info.clone(), sys_info.clone()],
+                        &[&[NFT_VAULT_SEED, &nft_mint_info.key.to_bytes(), &[stake_bump]]],
+                    )?;
+
+                    invoke_signed(
+                        &system_instruction::assign(&stake_address, program_id),
+                        &[stake_info.clone(), sys_info.clone()],
+                        &[&[NFT_VAULT_SEED, &nft_mint_info.key.to_bytes(), &[stake_bump]]],
+                    )?;
+                }
+                stake_bump
+            } else {
+                let stake_data: StakeData = load_account(stake_info, program_id)?;
+                assert_pda(
+                    stake_info,
+                    &[NFT_VAULT_SEED, &nft_mint_info.key.to_bytes()],
+                    stake_data.bump,
+                    program_id,
+                    0x10,
+                )?;
+
+                if stake_data.active != 0 {
+                    //nft is already staked
+                    return Err(ProgramError::Custom(0x110));
+                }
+                stake_data.bump
+            };
+
+            let stake_data = StakeData {
+                key: AccountKey::StakeV1 as u8,
+                padding: [0; 5],
+                last_accrual: clock.unix_timestamp as u64,
+                staker: *staker.key,
+                mint: *nft_mint_info.key,
+                active: 1,
+                withdrawn: 0,
+                harvested: 0,
+                staked_amount: 1,
+                max_reward,
+                position_mint: Pubkey::default(),
+                shares: 0,
+                boost_bps: 0,
+                referrer: Pubkey::default(),
+                bump: stake_bump,
+                rate: vault_data.rate,
+                maturity: position_maturity(clock.unix_timestamp as u64)?,
+                lock_start: clock.unix_timestamp as u64,
+                unlock_at: unlock_time(clock.unix_timestamp as u64, vault_data.min_period)?,
+                pending_reward: 0,
+                reserved: [0; 24],
+            };
+
+            store_account(stake_info, &stake_data)?;
+            store_account(vault_info, &vault_data)?;
+
+            if vault_nft_account_info.owner != token_program.key {
+                invoke(
+                    &spl_associated_token_account::create_associated_token_account(
+                        payer.key,
+                        vault_info.key,
+                        nft_mint_info.key,
+                    ),
+                    &[
+                        payer.clone(),
+                        vault_nft_account_info.clone(),
+                        vault_info.clone(),
+                        nft_mint_info.clone(),
+                        sys_info.clone(),
+                        token_program.clone(),
+                        rent_info.clone(),
+                        token_assoc.clone(),
+                    ],
+                )?;
+            }
+
+            invoke(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    staker_nft_account_info.key,
+                    vault_nft_account_info.key,
+                    staker.key,
+                    &[],
+                    1,
+                )?,
+                &[
+                    staker_nft_account_info.clone(),
+                    vault_nft_account_info.clone(),
+                    staker.clone(),
+                    token_program.clone(),
+                ],
+            )?;
+
+            assert_solvent(&vault_data, reward_token_account_info)?;
+
+            msg!("Nft staked {:?}", nft_mint_info.key);
+            StakeEvent::NftStaked(NftStaked {
+                staker: *staker.key,
+                nft_mint: *nft_mint_info.key,
+                stake: *stake_info.key,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
+
+        StakeInstruction::UnstakeNft => {
+            let staker = next_account_info(accounts_iter)?;
+            let stake_info = next_account_info(accounts_iter)?;
+            let staker_nft_account_info = next_account_info(accounts_iter)?;
+            let staker_token_account_info = next_account_info(accounts_iter)?;
+
+            let vault_info = next_account_info(accounts_iter)?;
+            let vault_nft_account_info = next_account_info(accounts_iter)?;
+            let reward_token_account_info = next_account_info(accounts_iter)?;
+
+            let nft_mint_info = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            let staker_nft_account = spl_associated_token_account::get_associated_token_address(
+                staker.key,
+                nft_mint_info.key,
+            );
+            let staker_token_account =
+                spl_associated_token_account::get_associated_token_address(staker.key, &mint);
+            let vault_nft_account = spl_associated_token_account::get_associated_token_address(
+                vault_info.key,
+                nft_mint_info.key,
+            );
+            let reward_token_account =
+                spl_associated_token_account::get_associated_token_address(vault_info.key, &mint);
+
+            if !staker.is_signer {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x11));
+            }
+
+            assert_writable(&[
+                stake_info,
+                staker_nft_account_info,
+                staker_token_account_info,
+                vault_info,
+                vault_nft_account_info,
+                reward_token_account_info,
+            ])?;
+
+            if *token_program.key != spl_token::id() {
+                //wrong token_program
+                return Err(ProgramError::Custom(0x345));
+            }
+
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(
+                vault_info,
+                &[NFT_VAULT_SEED],
+                vault_data.bump,
+                program_id,
+                0x70,
+            )?;
+            let vault_bump = vault_data.bump;
+            let emergency = vault_data.emergency != 0;
+
+            // like `EmergencyUnstake`, the emergency exit stays open while unstaking is paused
+            if vault_data.paused & PAUSE_UNSTAKE != 0 && !emergency {
+                //unstaking is paused
+                return Err(ProgramError::Custom(0x122));
+            }
+
+            let mut stake_data: StakeData = load_account(stake_info, program_id)?;
+            assert_pda(
+                stake_info,
+                &[NFT_VAULT_SEED, &nft_mint_info.key.to_bytes()],
+                stake_data.bump,
+                program_id,
+                0x60,
+            )?;
+
+            if staker_nft_account != *staker_nft_account_info.key
+                || staker_token_account != *staker_token_account_info.key
+            {
+                return Err(ProgramError::Custom(0x62));
+            }
+
+            if vault_nft_account != *vault_nft_account_info.key {
+                //wrong nft escrow
+                return Err(ProgramError::Custom(0x71));
+            }
+
+            if reward_token_account != *reward_token_account_info.key {
+                return Err(ProgramError::Custom(0x63));
+            }
+
+            if stake_data.active == 0 {
+                //staking is inactive
+                return Err(ProgramError::Custom(0x107));
+            }
+
+            if stake_data.staker != *staker.key {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x108));
+            }
+
+            let elapsed_duration =
+                match (clock.unix_timestamp as u64).checked_sub(stake_data.last_accrual) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+            // in emergency mode the nft is returned without a reward
+            let mut reward = 0;
+            if !emergency {
+                // the reserved year of rewards is all the nft earns
+                reward = accrued_reward(
+                    vault_data.rate,
+                    vault_data.reward_period,
+                    accrual_duration(&stake_data, clock.unix_timestamp as u64),
+                    vault_data.reward_period,
+                    vault_data.linear_accrual != 0,
+                )?;
+
+                // the nft itself can't be charged, the early-exit fee comes out of the reward
+                if is_locked(&stake_data, &vault_data, clock.unix_timestamp as u64) {
+                    reward = deduct_fee(reward, vault_data.early_withdrawal_fee)?;
+                }
+            }
+
+            msg!("seconds elapsed {:?}", elapsed_duration);
+            msg!(
+                "reward {:?}",
+                spl_token::amount_to_ui_amount(reward, DECIMALS)
+            );
+
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    vault_nft_account_info.key,
+                    staker_nft_account_info.key,
+                    vault_info.key,
+                    &[],
+                    1,
+                )?,
+                &[
+                    vault_nft_account_info.clone(),
+                    staker_nft_account_info.clone(),
+                    vault_info.clone(),
+                    token_program.clone(),
+                ],
+                &[&[NFT_VAULT_SEED, &[vault_bump]]],
+            )?;
+
+            if reward > 0 {
+                invoke_signed(
+                    &spl_token::instruction::transfer(
+                        token_program.key,
+                        reward_token_account_info.key,
+                        staker_token_account_info.key,
+                        vault_info.key,
+                        &[],
+                        reward,
+                    )?,
+                    &[
+                        reward_token_account_info.clone(),
+                        staker_token_account_info.clone(),
+                        vault_info.clone(),
+                        token_program.clone(),
+                    ],
+                    &[&[NFT_VAULT_SEED, &[vault_bump]]],
+                )?;
+            }
+
+            vault_data.total_obligations = match vault_data
+                .total_obligations
+                .checked_sub(stake_data.max_reward)
+            {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+
+            vault_data.total_staked = match vault_data.total_staked.checked_sub(1) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+
+            store_account(vault_info, &vault_data)?;
+            if !emergency {
+                assert_solvent(&vault_data, reward_token_account_info)?;
+            }
+
+            stake_data.active = 0;
+            stake_data.harvested = match stake_data.harvested.checked_add(reward) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+            stake_data.withdrawn = match stake_data.withdrawn.checked_add(reward) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+            stake_data.staked_amount = 0;
+            stake_data.max_reward = 0;
+            store_account(stake_info, &stake_data)?;
+
+            StakeEvent::NftUnstaked(NftUnstaked {
+                staker: *staker.key,
+                nft_mint: *nft_mint_info.key,
+                stake: *stake_info.key,
+                reward,
+                timestamp: clock.unix_timestamp as u64,
+                vault_balance: token_balance(reward_token_account_info)?,
+            })
+            .emit()?;
+        }
+
+        StakeInstruction::ClaimReferral => {
+            let referrer = next_account_info(accounts_iter)?;
+            let referral_info = next_account_info(accounts_iter)?;
+            let referrer_token_account_info = next_account_info(accounts_iter)?;
+
+            let vault_info = next_account_info(accounts_iter)?;
+            let vault_token_account_info = next_account_info(accounts_iter)?;
+
+            let mint_info = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+
+            let referrer_token_address =
+                spl_associated_token_account::get_associated_token_address(referrer.key, &mint);
+            let vault_token_address =
+                spl_associated_token_account::get_associated_token_address(vault_info.key, &mint);
+
+            if !referrer.is_signer {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x11));
+            }
+
+            assert_writable(&[
+                referral_info,
+                referrer_token_account_info,
+                vault_info,
+                vault_token_account_info,
+            ])?;
+
+            if *token_program.key != spl_token::id() {
+                //wrong token_program
+                return Err(ProgramError::Custom(0x345));
+            }
+
+            if referrer_token_address != *referrer_token_account_info.key {
+                return Err(ProgramError::Custom(0x62));
+            }
+
+            if vault_token_address != *vault_token_account_info.key {
+                return Err(ProgramError::Custom(0x63));
+            }
+
+            if *mint_info.key != mint {
+                return Err(ProgramError::Custom(0x67));
+            }
+
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(vault_info, &[VAULT_SEED], vault_data.bump, program_id, 0x61)?;
+            let vault_bump = vault_data.bump;
+
+            if vault_data.paused & PAUSE_CLAIM != 0 {
+                //claims are paused
+                return Err(ProgramError::Custom(0x121));
+            }
+
+            let mut referral_data: ReferralData = load_account(referral_info, program_id)?;
+            assert_pda(
+                referral_info,
+                &[REFERRAL_SEED, &referrer.key.to_bytes()],
+                referral_data.bump,
+                program_id,
+                0x75,
+            )?;
+
+            if referral_data.pending == 0 {
+                //nothing to claim
+                return Err(ProgramError::Custom(0x77));
+            }
+
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    vault_token_account_info.key,
+                    referrer_token_account_info.key,
+                    vault_info.key,
+                    &[],
+                    referral_data.pending,
+                )?,
+                &[
+                    vault_token_account_info.clone(),
+                    referrer_token_account_info.clone(),
+                    vault_info.clone(),
+                    token_program.clone(),
+                ],
+                &[&[VAULT_SEED, &[vault_bump]]],
+            )?;
+
+            vault_data.total_obligations = match vault_data
+                .total_obligations
+                .checked_sub(referral_data.pending)
+            {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+
+            msg!(
+                "referral claimed {:?}",
+                spl_token::amount_to_ui_amount(referral_data.pending, DECIMALS)
+            );
+            StakeEvent::ReferralClaimed(ReferralClaimed {
+                referrer: *referrer.key,
+                amount: referral_data.pending,
+                timestamp: Clock::get()?.unix_timestamp as u64,
+                vault_balance: token_balance(vault_token_account_info)?,
+            })
+            .emit()?;
+            referral_data.pending = 0;
+
+            store_account(referral_info, &referral_data)?;
+            store_account(vault_info, &vault_data)?;
+            assert_solvent(&vault_data, vault_token_account_info)?;
+        }
+
+        StakeInstruction::MigrateAccount => {
+            let payer = next_account_info(accounts_iter)?;
+            let account_info = next_account_info(accounts_iter)?;
+            let sys_info = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x14));
+            }
+
+            assert_writable(&[payer, account_info])?;
+            assert_program(sys_info, &system_program::id())?;
+
+            if account_info.owner != program_id {
+                return Err(ProgramError::IncorrectProgramId);
+            }
+
+            // legacy accounts carry no key, so their layout is told apart by size
+            let data_len = account_info.data_len() as u64;
+            if data_len == LEGACY_STAKE_SIZE {
+                let legacy: LegacyStakeData =
+                    try_from_slice_unchecked(&account_info.data.borrow())?;
+                let (stake_address, stake_bump) =
+                    Pubkey::find_program_address(&[&legacy.staker.to_bytes()], program_id);
+
+                if stake_address != *account_info.key {
+                    //wrong stake account
+                    return Err(ProgramError::Custom(0x60));
+                }
+
+                let stake_data = StakeData {
+                    key: AccountKey::StakeV1 as u8,
+                    padding: [0; 5],
+                    last_accrual: legacy.timestamp,
+                    staker: legacy.staker,
+                    mint: legacy.mint,
+                    active: legacy.active as u8,
+                    withdrawn: legacy.withdrawn,
+                    harvested: legacy.harvested,
+                    staked_amount: legacy.staked_amount,
+                    max_reward: legacy.max_reward,
+                    position_mint: Pubkey::default(),
+                    shares: 0,
+                    boost_bps: 0,
+                    referrer: Pubkey::default(),
+                    bump: stake_bump,
+                    rate: 0,
+                    maturity: 0,
+                    lock_start: 0,
+                    unlock_at: 0,
+                    pending_reward: 0,
+                    reserved: [0; 24],
+                };
+
+                resize_account(account_info, payer, sys_info, STAKE_SIZE)?;
+                store_account(account_info, &stake_data)?;
+                msg!("Stake account migrated to {:?}", AccountKey::StakeV1);
+                StakeEvent::AccountMigrated(AccountMigrated {
+                    account: *account_info.key,
+                    key: AccountKey::StakeV1 as u8,
+                })
+                .emit()?;
+            } else if data_len == LEGACY_VAULT_SIZE {
+                let legacy: LegacyVaultData =
+                    try_from_slice_unchecked(&account_info.data.borrow())?;
+                let (vault_address, vault_bump) =
+                    Pubkey::find_program_address(&[VAULT_SEED], program_id);
+
+                if vault_address != *account_info.key {
+                    //wrong vault account
+                    return Err(ProgramError::Custom(0x61));
+                }
+
+                let vault_data = VaultData {
+                    key: AccountKey::VaultV1 as u8,
+                    emergency: 0,
+                    paused: 0,
+                    linear_accrual: 0,
+                    lock_policy: LOCK_EXTEND,
+                    mint: legacy.mint,
+                    min_period: legacy.min_period,
+                    reward_period: legacy.reward_period,
+                    rate: legacy_rate_bps(legacy.rate)?,
+                    early_withdrawal_fee: DEFAULT_EARLY_EXIT_FEE_BPS,
+                    total_obligations: legacy.total_obligations,
+                    total_staked: legacy.total_staked,
+                    position_nft: 0,
+                    receipt_mint: Pubkey::default(),
+                    share_accounting: 0,
+                    total_shares: 0,
+                    last_accrual: clock.unix_timestamp as u64,
+                    collection: Pubkey::default(),
+                    boost_collection: Pubkey::default(),
+                    boost_bps: 0,
+                    referral_bps: 0,
+                    bump: vault_bump,
+                    multisig: Pubkey::default(),
+                    rollover_policy: ROLLOVER_COMPOUND,
+                    padding: [0; 7],
+                    pool_reserve: 0,
+                    reserved: [0; 16],
+                };
+
+                resize_account(account_info, payer, sys_info, VAULT_SIZE)?;
+                store_account(account_info, &vault_data)?;
+                msg!("Vault account migrated to {:?}", AccountKey::VaultV1);
+                StakeEvent::AccountMigrated(AccountMigrated {
+                    account: *account_info.key,
+                    key: AccountKey::VaultV1 as u8,
+                })
+                .emit()?;
+            } else {
+                //account is already on the current layout
+                return Err(ProgramError::Custom(0x114));
+            }
+        }
+
+        StakeInstruction::SweepToken { amount } => {
+            let admin_info = next_account_info(accounts_iter)?;
+            let vault_info = next_account_info(accounts_iter)?;
+            let source_token_account_info = next_account_info(accounts_iter)?;
+            let destination_token_account_info = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            assert_writable(&[source_token_account_info, destination_token_account_info])?;
+
+            if *token_program.key != spl_token::id() {
+                //wrong token_program
+                return Err(ProgramError::Custom(0x345));
+            }
+
+            let vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(vault_info, &[VAULT_SEED], vault_data.bump, program_id, 0x61)?;
+            let vault_bump = vault_data.bump;
+
+            assert_admin(
+                program_id,
+                admin_info,
+                &vault_data.multisig,
+                accounts_iter,
+                0x231,
+            )?;
+
+            if source_token_account_info.owner != token_program.key {
+                //wrong source_token_account_info
+                return Err(ProgramError::Custom(0x63));
+            }
+
+            let source_token_account =
+                spl_token::state::Account::unpack(&source_token_account_info.data.borrow())?;
+
+            if source_token_account.owner != *vault_info.key {
+                //source is not held by the vault
+                return Err(ProgramError::Custom(0x63));
+            }
+
+            // stakers' principal and rewards are only ever moved by their own instructions
+            if source_token_account.mint == mint || source_token_account.mint == vault_data.mint {
+                return Err(ProgramError::Custom(0x116));
+            }
+
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    source_token_account_info.key,
+                    destination_token_account_info.key,
+                    vault_info.key,
+                    &[],
+                    amount,
+                )?,
+                &[
+                    source_token_account_info.clone(),
+                    destination_token_account_info.clone(),
+                    vault_info.clone(),
+                    token_program.clone(),
+                ],
+                &[&[VAULT_SEED, &[vault_bump]]],
+            )?;
+
+            StakeEvent::TokenSwept(TokenSwept {
+                admin: *admin_info.key,
+                mint: source_token_account.mint,
+                destination: *destination_token_account_info.key,
+                amount,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
+
+        StakeInstruction::SetEmergency { enabled } => {
+            let admin_info = next_account_info(accounts_iter)?;
+            let vault_info = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            assert_writable(&[vault_info])?;
+
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_vault(vault_info, vault_data.bump, program_id, 0x61)?;
+
+            assert_admin(
+                program_id,
+                admin_info,
+                &vault_data.multisig,
+                accounts_iter,
+                0x231,
+            )?;
+
+            vault_data.emergency = enabled as u8;
+            store_account(vault_info, &vault_data)?;
+
+            msg!("Emergency mode {:?}", enabled);
+            StakeEvent::EmergencySet(EmergencySet {
+                admin: *admin_info.key,
+                enabled,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
+
+        // Returns the principal of a position with no reward and no early-exit fee. Only
+        // available in emergency mode, when rewards may be what the vault can no longer pay.
+        // Pausing unstakes doesn't apply here, so this exit is never closed.
+        StakeInstruction::EmergencyUnstake => {
+            let staker = next_account_info(accounts_iter)?;
+            let stake_info = next_account_info(accounts_iter)?;
+            let staker_token_account_info = next_account_info(accounts_iter)?;
+
+            let vault_info = next_account_info(accounts_iter)?;
+            let vault_token_account_info = next_account_info(accounts_iter)?;
+
+            let mint_info = next_account_info(accounts_iter)?;
+            let token_program = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            if !staker.is_signer {
+                //unauthorized access
+                return Err(ProgramError::Custom(0x11));
+            }
+
+            assert_writable(&[
+                stake_info,
+                staker_token_account_info,
+                vault_info,
+                vault_token_account_info,
+            ])?;
+
+            if *token_program.key != spl_token::id() {
+                //wrong token_program
+                return Err(ProgramError::Custom(0x345));
+            }
+
+            if spl_associated_token_account::get_associated_token_address(staker.key, &mint)
+                != *staker_token_account_info.key
+            {
+                //wrong staker_token_account
+                return Err(ProgramError::Custom(0x62));
+            }
+
+            if spl_associated_token_account::get_associated_token_address(vault_info.key, &mint)
+                != *vault_token_account_info.key
+            {
+                //wrong vault_token_account
+                return Err(ProgramError::Custom(0x63));
+            }
+
+            if mint != *mint_info.key {
+                //wrong mint_info
+                return Err(ProgramError::Custom(0x67));
+            }
+
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(vault_info, &[VAULT_SEED], vault_data.bump, program_id, 0x61)?;
+            let vault_bump = vault_data.bump;
+
+            if vault_data.emergency == 0 {
+                //vault is not in emergency mode
+                return Err(ProgramError::Custom(0x118));
+            }
+
+            let position_accounts = if vault_data.position_nft != 0 {
+                let position_mint_info = next_account_info(accounts_iter)?;
+                let position_token_account_info = next_account_info(accounts_iter)?;
+                assert_writable(&[position_mint_info, position_token_account_info])?;
+                Some((position_mint_info, position_token_account_info))
+            } else {
+                None
+            };
+
+            let receipt_accounts = if vault_data.receipt_mint != Pubkey::default() {
+                let receipt_mint_info = next_account_info(accounts_iter)?;
+                let receipt_token_account_info = next_account_info(accounts_iter)?;
+                assert_writable(&[receipt_mint_info, receipt_token_account_info])?;
+                Some((receipt_mint_info, receipt_token_account_info))
+            } else {
+                None
+            };
+
+            // nft positions are keyed by their mint, the rest by the staker they were opened for
+            let mut stake_data: StakeData = load_account(stake_info, program_id)?;
+            let stake_seed = match position_accounts {
+                Some((position_mint_info, _)) => position_mint_info.key.to_bytes(),
+                None => stake_data.staker.to_bytes(),
+            };
+            assert_pda(
+                stake_info,
+                &[&stake_seed],
+                stake_data.bump,
+                program_id,
+                0x60,
+            )?;
+
+            if stake_data.active == 0 {
+                //staking is inactive
+                return Err(ProgramError::Custom(0x107));
+            }
+
+            if let Some((position_mint_info, position_token_account_info)) = position_accounts {
+                if stake_data.position_mint != *position_mint_info.key {
+                    //wrong position_mint_info
+                    return Err(ProgramError::Custom(0x68));
+                }
+
+                if position_token_account_info.owner != token_program.key {
+                    //wrong position_token_account_info
+                    return Err(ProgramError::Custom(0x69));
+                }
+
+                let position_token_account =
+                    spl_token::state::Account::unpack(&position_token_account_info.data.borrow())?;
+
+                if position_token_account.mint != *position_mint_info.key
+                    || position_token_account.owner != *staker.key
+                    || position_token_account.amount != 1
+                {
+                    //signer does not hold the position nft
+                    return Err(ProgramError::Custom(0x108));
+                }
+            } else if stake_data.staker != *staker.key
+                && (receipt_accounts.is_none() || stake_data.shares == 0)
+            {
+                //unauthorized access, unless redeeming with the position's receipt shares
+                return Err(ProgramError::Custom(0x108));
+            }
+
+            // in share mode the position may have compounded, only what was deposited returns
+            let principal = if vault_data.share_accounting != 0 {
+                amount_for_shares(
+                    stake_data.shares,
+                    vault_data.total_shares,
+                    vault_data.total_staked,
+                )?
+                .min(stake_data.staked_amount)
+            } else {
+                stake_data.staked_amount
+            };
+
+            if token_balance(vault_token_account_info)? < principal {
+                //vault can't cover the withdrawal
+                return Err(ProgramError::Custom(0x115));
+            }
+
+            invoke_signed(
+                &spl_token::instruction::transfer(
+                    token_program.key,
+                    vault_token_account_info.key,
+                    staker_token_account_info.key,
+                    vault_info.key,
+                    &[],
+                    principal,
+                )?,
+                &[
+                    vault_token_account_info.clone(),
+                    staker_token_account_info.clone(),
+                    vault_info.clone(),
+                    token_program.clone(),
+                ],
+                &[&[VAULT_SEED, &[vault_bump]]],
+            )?;
+
+            if let Some((position_mint_info, position_token_account_info)) = position_accounts {
+                invoke(
+                    &spl_token::instruction::burn(
+                        token_program.key,
+                        position_token_account_info.key,
+                        position_mint_info.key,
+                        staker.key,
+                        &[],
+                        1,
+                    )?,
+                    &[
+                        position_token_account_info.clone(),
+                        position_mint_info.clone(),
+                        staker.clone(),
+                        token_program.clone(),
+                    ],
+                )?;
+            }
+
+            if let Some((receipt_mint_info, receipt_token_account_info)) = receipt_accounts {
+                if vault_data.receipt_mint != *receipt_mint_info.key {
+                    //wrong receipt_mint_info
+                    return Err(ProgramError::Custom(0x6a));
+                }
+
+                invoke(
+                    &spl_token::instruction::burn(
+                        token_program.key,
+                        receipt_token_account_info.key,
+                        receipt_mint_info.key,
+                        staker.key,
+                        &[],
+                        stake_data.shares,
+                    )?,
+                    &[
+                        receipt_token_account_info.clone(),
+                        receipt_mint_info.clone(),
+                        staker.clone(),
+                        token_program.clone(),
+                    ],
+                )?;
+            }
+
+            if vault_data.share_accounting != 0 {
+                release_pool_reserve(&mut vault_data, stake_data.shares)?;
+
+                // anything the position earned stays in the pool for the remaining stakers
+                vault_data.total_staked = match vault_data.total_staked.checked_sub(principal) {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                vault_data.total_shares =
+                    match vault_data.total_shares.checked_sub(stake_data.shares) {
+                        Some(x) => x,
+                        _ => return Err(ProgramError::Custom(0x109)),
+                    };
+
+                if vault_data.total_shares == 0 {
+                    vault_data.total_staked = 0;
+                }
+            } else {
+                reprice_legacy_position(&mut stake_data, &vault_data)?;
+
+                // the forfeited reward, pending rewards included, is released from the obligations
+                vault_data.total_obligations = match vault_data
+                    .total_obligations
+                    .checked_sub(stake_data.max_reward)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                vault_data.total_obligations = match vault_data
+                    .total_obligations
+                    .checked_sub(stake_data.pending_reward)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+
+                vault_data.total_staked = match vault_data
+                    .total_staked
+                    .checked_sub(stake_data.staked_amount)
+                {
+                    Some(x) => x,
+                    _ => return Err(ProgramError::Custom(0x109)),
+                };
+            }
+
+            // no solvency check here: exits must stay open even when rewards are underfunded
+            store_account(vault_info, &vault_data)?;
+
+            StakeEvent::EmergencyUnstaked(EmergencyUnstaked {
+                staker: *staker.key,
+                stake: *stake_info.key,
+                principal,
+                timestamp: clock.unix_timestamp as u64,
+                vault_balance: token_balance(vault_token_account_info)?,
+            })
+            .emit()?;
+
+            stake_data.active = 0;
+            stake_data.withdrawn = match stake_data.withdrawn.checked_add(principal) {
+                Some(x) => x,
+                _ => return Err(ProgramError::Custom(0x109)),
+            };
+            stake_data.staked_amount = 0;
+            stake_data.max_reward = 0;
+            stake_data.shares = 0;
+            stake_data.boost_bps = 0;
+            stake_data.pending_reward = 0;
+            store_account(stake_info, &stake_data)?;
+        }
+
+        StakeInstruction::SetPause { paused } => {
+            let admin_info = next_account_info(accounts_iter)?;
+            let vault_info = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            assert_writable(&[vault_info])?;
+
+            // either vault can be paused, nft vaults use the stake and unstake bits. Emergency mode
+            // lifts the unstake bit, so staked nfts can always be taken back without a reward.
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(vault_info, &[VAULT_SEED], vault_data.bump, program_id, 0x61).or_else(
+                |_| {
+                    assert_pda(
+                        vault_info,
+                        &[NFT_VAULT_SEED],
+                        vault_data.bump,
+                        program_id,
+                        0x61,
+                    )
+                },
+            )?;
+
+            assert_admin(
+                program_id,
+                admin_info,
+                &vault_data.multisig,
+                accounts_iter,
+                0x231,
+            )?;
+
+            vault_data.paused = paused;
+            store_account(vault_info, &vault_data)?;
+
+            msg!("Paused operations {:#06b}", paused);
+            StakeEvent::PauseSet(PauseSet {
+                admin: *admin_info.key,
+                vault: *vault_info.key,
+                paused,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
+
+        // Replaces the admin signers of a vault. Approved by `ADMIN_PK` while the vault has no
+        // multisig and by a quorum of the current signers once it has; an empty list with a zero
+        // threshold hands the vault back to `ADMIN_PK`.
+        StakeInstruction::SetMultisig { signers, threshold } => {
+            let admin_info = next_account_info(accounts_iter)?;
+            let payer = next_account_info(accounts_iter)?;
+            let vault_info = next_account_info(accounts_iter)?;
+            let multisig_info = next_account_info(accounts_iter)?;
+            let sys_info = next_account_info(accounts_iter)?;
+
+            let clock = Clock::get()?;
+
+            if !payer.is_signer {
+                //payer must sign for rent
+                return Err(ProgramError::Custom(0x03));
+            }
+
+            assert_writable(&[payer, vault_info, multisig_info])?;
+            assert_program(sys_info, &system_program::id())?;
+
+            let mut vault_data: VaultData = load_account(vault_info, program_id)?;
+            assert_pda(vault_info, &[VAULT_SEED], vault_data.bump, program_id, 0x61).or_else(
+                |_| {
+                    assert_pda(
+                        vault_info,
+                        &[NFT_VAULT_SEED],
+                        vault_data.bump,
+                        program_id,
+                        0x61,
+                    )
+                },
+            )?;
+
+            let (multisig_address, multisig_bump) = Pubkey::find_program_address(
+                &[MULTISIG_SEED, &vault_info.key.to_bytes()],
+                program_id,
+            );
+
+            if multisig_address != *multisig_info.key {
+                //wrong multisig account
+                return Err(ProgramError::Custom(0x234));
+            }
+
+            // the remaining accounts are co-signers
+            if vault_data.multisig == Pubkey::default() {
+                assert_admin(
+                    program_id,
+                    admin_info,
+                    &vault_data.multisig,
+                    accounts_iter,
+                    0x231,
+                )?;
+            } else {
+                let multisig_data: MultisigData = load_account(multisig_info, program_id)?;
+                assert_quorum(&multisig_data, admin_info, accounts_iter.as_slice())?;
+            }
+
+            let disable = signers.is_empty() && threshold == 0;
+            let duplicate = signers
+                .iter()
+                .enumerate()
+                .any(|(i, signer)| signers[..i].contains(signer));
+            if !disable
+                && (threshold == 0
+                    || threshold as usize > signers.len()
+                    || signers.len() > MAX_SIGNERS
+                    || duplicate)
+            {
+                //invalid signer set
+                return Err(ProgramError::Custom(0x233));
+            }
+
+            if multisig_info.owner != program_id {
+                msg!("No multisig account found, creating...");
+                let size: u64 = MULTISIG_SIZE;
+                let required_lamports = Rent::get()?
+                    .minimum_balance(size as usize)
+                    .max(1)
+                    .saturating_sub(multisig_info.lamports());
+                invoke(
+                    &system_instruction::transfer(payer.key, &multisig_address, required_lamports),
+                    &[payer.clone(), multisig_info.clone(), sys_info.clone()],
+                )?;
+                invoke_signed(
+                    &system_instruction::allocate(&multisig_address, size),
+                    &[multisig_info.clone(), sys_info.clone()],
+                    &[&[MULTISIG_SEED, &vault_info.key.to_bytes(), &[multisig_bump]]],
+                )?;
+                invoke_signed(
+                    &system_instruction::assign(&multisig_address, program_id),
+                    &[multisig_info.clone(), sys_info.clone()],
+                    &[&[MULTISIG_SEED, &vault_info.key.to_bytes(), &[multisig_bump]]],
+                )?;
+            }
+
+            let mut multisig_data = MultisigData {
+                key: AccountKey::MultisigV1 as u8,
+                threshold,
+                signer_count: signers.len() as u8,
+                bump: multisig_bump,
+                padding: [0; 4],
+                vault: *vault_info.key,
+                signers: [Pubkey::default(); MAX_SIGNERS],
+                reserved: [0; 64],
+            };
+            multisig_data.signers[..signers.len()].copy_from_slice(&signers);
+            store_account(multisig_info, &multisig_data)?;
+
+            vault_data.multisig = if disable {
+                Pubkey::default()
+            } else {
+                multisig_address
+            };
+            store_account(vault_info, &vault_data)?;
+
+            msg!("Multisig {} of {}", threshold, signers.len());
+            StakeEvent::MultisigSet(MultisigSet {
+                admin: *admin_info.key,
+                vault: *vault_info.key,
+                multisig: vault_data.multisig,
+                signers,
+                threshold,
+                timestamp: clock.unix_timestamp as u64,
+            })
+            .emit()?;
+        }
+    };
+
+    Ok(())
+}
+
+// Multisig of a vault account being regenerated, or none for a new or legacy vault.
+fn existing_multisig(vault_info: &AccountInfo, program_id: &Pubkey) -> Pubkey {
+    if vault_info.owner != program_id {
+        return Pubkey::default();
+    }
+
+    load_account::<VaultData>(vault_info, program_id)
+        .map(|vault_data| vault_data.multisig)
+        .unwrap_or_default()
+}
+
+// End of a `min_period` lock starting at `lock_start`.
+fn unlock_time(lock_start: u64, min_period: u64) -> Result<u64, ProgramError> {
+    lock_start
+        .checked_add(min_period)
+        .ok_or_else(|| MathError::Overflow.into())
+}
+
+// Whether leaving now still pays the early-exit fee. Positions opened before locks were
+// recorded are locked from their last accrual, as they always were.
+fn is_locked(stake_data: &StakeData, vault_data: &VaultData, now: u64) -> bool {
+    let unlock_at = if stake_data.unlock_at != 0 {
+        stake_data.unlock_at
+    } else {
+        stake_data
+            .last_accrual
+            .saturating_add(vault_data.min_period)
+    };
+
+    now < unlock_at
+}
+
+// Moves the lock of a position topped up with `amount`, per the vault's lock policy. Must run
+// before `active`, `staked_amount` and `last_accrual` are updated.
+fn relock(
+    stake_data: &mut StakeData,
+    vault_data: &VaultData,
+    amount: u64,
+    now: u64,
+) -> ProgramResult {
+    let lock_start = if stake_data.lock_start != 0 {
+        stake_data.lock_start
+    } else {
+        stake_data.last_accrual
+    };
+
+    let lock_start = match vault_data.lock_policy {
+        // a closed position being reopened starts a fresh lock
+        _ if stake_data.active == 0 => now,
+        LOCK_KEEP => lock_start,
+        // the lock starts at the stake-weighted average of the old start and now
+        LOCK_WEIGHTED => match stake_data.staked_amount.checked_add(amount) {
+            Some(0) => now,
+            Some(total) => lock_start
+                .checked_add(mul_div(now.saturating_sub(lock_start), amount, total)?)
+                .ok_or(MathError::Overflow)?,
+            None => return Err(MathError::Overflow.into()),
+        },
+        _ => now,
+    };
+
+    stake_data.lock_start = lock_start;
+    stake_data.unlock_at = unlock_time(lock_start, vault_data.min_period)?;
+
+    Ok(())
+}
+
+// Positions run for a fixed one-year term, the span `max_reward` reserves for.
+fn position_maturity(opened: u64) -> Result<u64, ProgramError> {
+    opened
+        .checked_add(YEAR)
+        .ok_or_else(|| MathError::Overflow.into())
+}
+
+// Seconds a position has accrued for, stopping at maturity so it never earns more than its
+// reserved `max_reward`. Positions opened before maturity was recorded have the same term.
+fn accrual_duration(stake_data: &StakeData, now: u64) -> u64 {
+    let maturity = if stake_data.maturity != 0 {
+        stake_data.maturity
+    } else {
+        stake_data.last_accrual.saturating_add(YEAR)
+    };
+
+    now.min(maturity).saturating_sub(stake_data.last_accrual)
+}
+
+// Positions opened before stakes recorded their rate (`rate == 0`) had `max_reward` priced at a
+// fixed 2x while the vault reserved its own rate. They are repriced at the vault rate, which is
+// what was backed, capped at the obligations still outstanding so settling one can't underflow
+// them.
+fn reprice_legacy_position(stake_data: &mut StakeData, vault_data: &VaultData) -> ProgramResult {
+    if stake_data.rate != 0 {
+        return Ok(());
+    }
+
+    let max_reward = apply_boost(
+        annual_reward(stake_data.staked_amount, vault_data.rate)?,
+        stake_data.boost_bps,
+    )?;
+    stake_data.max_reward = max_reward.min(vault_data.total_obligations);
+    stake_data.rate = vault_data.rate;
+
+    Ok(())
+}
+
+// Vaults from before accounts were versioned stored their rate as a whole multiple of the stake paid per
+// year and charged a fixed fee whatever `early_withdrawal_fee` held. Nft vault rates are token
+// amounts and carry over as they are.
+fn legacy_rate_bps(rate: u64) -> Result<u64, ProgramError> {
+    rate.checked_mul(BPS_DENOMINATOR)
+        .ok_or_else(|| MathError::Overflow.into())
+}
+
+// Compounds the vault rate into the pool for every whole reward period since the last
+// accrual, or every second for linear vaults. Growth is drawn from the reserve deposits set
+// aside in `pool_reserve`, then from any surplus the vault holds, and is capped by both.
+fn accrue_pool(vault_data: &mut VaultData, now: u64, vault_balance: u64) -> ProgramResult {
+    let linear = vault_data.linear_accrual != 0;
+    let elapsed = now
+        .checked_sub(vault_data.last_accrual)
+        .ok_or(MathError::Overflow)?;
+
+    let annual_growth = annual_reward(vault_data.total_staked, vault_data.rate)?;
+    let growth = accrued_reward(
+        annual_growth,
+        YEAR,
+        elapsed,
+        vault_data.reward_period,
+        linear,
+    )?;
+
+    // time that didn't add a whole token unit stays unaccrued, unless the pool is empty
+    if growth == 0 && vault_data.total_staked != 0 {
+        return Ok(());
+    }
+
+    let surplus = vault_balance.saturating_sub(
+        vault_data
+            .total_staked
+            .saturating_add(vault_data.total_obligations),
+    );
+    let reserve = vault_data.pool_reserve;
+    let growth = growth.min(reserve.saturating_add(surplus));
+
+    let drawn = growth.min(reserve);
+    vault_data.pool_reserve = reserve - drawn;
+    vault_data.total_obligations = vault_data
+        .total_obligations
+        .checked_sub(drawn)
+        .ok_or(MathError::Overflow)?;
+    vault_data.total_staked = vault_data
+        .total_staked
+        .checked_add(growth)
+        .ok_or(MathError::Overflow)?;
+    // discrete vaults carry the partial period over to the next accrual
+    vault_data.last_accrual = if linear {
+        now
+    } else {
+        now - elapsed % vault_data.reward_period
+    };
+
+    Ok(())
+}
+
+// Releases the reserved pool growth backing `shares` that are leaving the pool.
+fn release_pool_reserve(vault_data: &mut VaultData, shares: u64) -> ProgramResult {
+    if vault_data.total_shares == 0 {
+        return Ok(());
+    }
+
+    let released = mul_div(vault_data.pool_reserve, shares, vault_data.total_shares)?;
+    vault_data.pool_reserve = vault_data
+        .pool_reserve
+        .checked_sub(released)
+        .ok_or(MathError::Overflow)?;
+    vault_data.total_obligations = vault_data
+        .total_obligations
+        .checked_sub(released)
+        .ok_or(MathError::Overflow)?;
+
+    Ok(())
+}
+
+// Reads the amount held by an spl token account.
+fn token_balance(token_account_info: &AccountInfo) -> Result<u64, ProgramError> {
+    Ok(spl_token::state::Account::unpack_from_slice(&token_account_info.data.borrow())?.amount)
+}
+
+// Tops up rent from `payer` and reallocates `account_info` to `size` bytes.
+fn resize_account<'a>(
+    account_info: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    sys_info: &AccountInfo<'a>,
+    size: u64,
+) -> ProgramResult {
+    let required_lamports = Rent::get()?
+        .minimum_balance(size as usize)
+        .saturating_sub(account_info.lamports());
+
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer.key, account_info.key, required_lamports),
+            &[payer.clone(), account_info.clone(), sys_info.clone()],
+        )?;
+    }
+
+    account_info.realloc(size as usize, true)
+}
+
+// Credits the referrer with `referral_bps` of a settled reward. The credit is reserved as an
+// obligation out of the vault surplus and capped by it, so it never eats into stakers' funds.
+fn credit_referrer(
+    program_id: &Pubkey,
+    vault_data: &mut VaultData,
+    referral_info: &AccountInfo,
+    referrer: &Pubkey,
+    reward: u64,
+    vault_balance: u64,
+) -> ProgramResult {
+    let mut referral_data: ReferralData = load_account(referral_info, program_id)?;
+    assert_pda(
+        referral_info,
+        &[REFERRAL_SEED, &referrer.to_bytes()],
+        referral_data.bump,
+        program_id,
+        0x75,
+    )?;
+
+    let credit = bps_of(reward, vault_data.referral_bps)?;
+
+    let surplus = vault_balance.saturating_sub(
+        vault_data
+            .total_staked
+            .saturating_add(vault_data.total_obligations),
+    );
+    let credit = credit.min(surplus);
+
+    referral_data.pending = match referral_data.pending.checked_add(credit) {
+        Some(x) => x,
+        _ => return Err(ProgramError::Custom(0x109)),
+    };
+    referral_data.total_earned = match referral_data.total_earned.checked_add(credit) {
+        Some(x) => x,
+        _ => return Err(ProgramError::Custom(0x109)),
+    };
+    vault_data.total_obligations = match vault_data.total_obligations.checked_add(credit) {
+        Some(x) => x,
+        _ => return Err(ProgramError::Custom(0x109)),
+    };
+
+    msg!(
+        "referral credit {:?}",
+        spl_token::amount_to_ui_amount(credit, DECIMALS)
+    );
+    StakeEvent::ReferralCredited(ReferralCredited {
+        referrer: *referrer,
+        amount: credit,
+        pending: referral_data.pending,
+    })
+    .emit()?;
+    store_account(referral_info, &referral_data)?;
+
+    Ok(())
+}
+
+// Checks that `owner` holds, through `token_account_info`, an nft of `collection`.
+fn verify_boost_holder(
+    owner: &Pubkey,
+    token_account_info: &AccountInfo,
+    nft_mint_info: &AccountInfo,
+    metadata_info: &AccountInfo,
+    collection: &Pubkey,
+) -> ProgramResult {
+    if *token_account_info.owner != spl_token::id() {
+        //wrong boost token account
+        return Err(ProgramError::Custom(0x74));
+    }
+
+    let token_account = spl_token::state::Account::unpack(&token_account_info.data.borrow())?;
+    if token_account.mint != *nft_mint_info.key
+        || token_account.owner != *owner
+        || token_account.amount != 1
+    {
+        //staker does not hold the boost nft
+        return Err(ProgramError::Custom(0x74));
+    }
+
+    verify_collection_nft(nft_mint_info, metadata_info, collection)
+}
+
+// Checks that `nft_mint_info` is a 1-of-1 mint whose token metadata lists `collection` as a
+// verified creator, which is how collections are expressed in spl-token-metadata.
+fn verify_collection_nft(
+    nft_mint_info: &AccountInfo,
+    metadata_info: &AccountInfo,
+    collection: &Pubkey,
+) -> ProgramResult {
+    let (metadata_address, _metadata_bump) = Pubkey::find_program_address(
+        &[
+            spl_token_metadata::state::PREFIX.as_bytes(),
+            &spl_token_metadata::id().to_bytes(),
+            &nft_mint_info.key.to_bytes(),
+        ],
+        &spl_token_metadata::id(),
+    );
+
+    if metadata_address != *metadata_info.key || *metadata_info.owner != spl_token_metadata::id() {
+        //wrong metadata_info
+        return Err(ProgramError::Custom(0x66));
+    }
+
+    if *nft_mint_info.owner != spl_token::id() {
+        //not an nft
+        return Err(ProgramError::Custom(0x73));
+    }
+
+    let nft_mint = spl_token::state::Mint::unpack(&nft_mint_info.data.borrow())?;
+    if nft_mint.decimals != 0 || nft_mint.supply != 1 {
+        //not an nft
+        return Err(ProgramError::Custom(0x73));
+    }
+
+    let metadata: spl_token_metadata::state::Metadata =
+        try_from_slice_unchecked(&metadata_info.data.borrow())?;
+
+    if metadata.mint != *nft_mint_info.key {
+        //wrong metadata_info
+        return Err(ProgramError::Custom(0x66));
+    }
+
+    let verified = match metadata.data.creators {
+        Some(creators) => creators
+            .iter()
+            .any(|creator| creator.verified && creator.address == *collection),
+        None => false,
+    };
+
+    if !verified {
+        //nft is not part of the collection
+        return Err(ProgramError::Custom(0x72));
+    }
 
     Ok(())
 }
@@ -0,0 +1,237 @@
+// Reward, fee and share arithmetic. Products are taken in u128 and only the result is narrowed
+// back to u64; rates, fees and boosts are in basis points. Failures are `MathError`s, which
+// convert into the program's custom error codes.
+use solana_program::program_error::ProgramError;
+
+use crate::{BPS_DENOMINATOR, YEAR};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MathError {
+    // the result doesn't fit in a u64, or a subtraction went below zero
+    Overflow,
+    // a zero reward period, or a ratio over an empty pool
+    DivideByZero,
+}
+
+impl From<MathError> for ProgramError {
+    fn from(error: MathError) -> Self {
+        match error {
+            MathError::Overflow => ProgramError::Custom(0x109),
+            MathError::DivideByZero => ProgramError::Custom(0x10a),
+        }
+    }
+}
+
+// `a * b / c`, rounded down.
+pub(crate) fn mul_div(a: u64, b: u64, c: u64) -> Result<u64, MathError> {
+    if c == 0 {
+        return Err(MathError::DivideByZero);
+    }
+
+    let x = (a as u128) * (b as u128) / (c as u128);
+    u64::try_from(x).map_err(|_| MathError::Overflow)
+}
+
+// `bps` basis points of `amount`.
+pub(crate) fn bps_of(amount: u64, bps: u64) -> Result<u64, MathError> {
+    mul_div(amount, bps, BPS_DENOMINATOR)
+}
+
+// `amount` less a fee of `fee_bps`.
+pub(crate) fn deduct_fee(amount: u64, fee_bps: u64) -> Result<u64, MathError> {
+    amount
+        .checked_sub(bps_of(amount, fee_bps)?)
+        .ok_or(MathError::Overflow)
+}
+
+// Scales `amount` up by `boost_bps`.
+pub(crate) fn apply_boost(amount: u64, boost_bps: u64) -> Result<u64, MathError> {
+    let scale = BPS_DENOMINATOR
+        .checked_add(boost_bps)
+        .ok_or(MathError::Overflow)?;
+    mul_div(amount, scale, BPS_DENOMINATOR)
+}
+
+// Reverses `apply_boost`, used when a recorded boost can't be proven at settlement.
+pub(crate) fn remove_boost(amount: u64, boost_bps: u64) -> Result<u64, MathError> {
+    let scale = BPS_DENOMINATOR
+        .checked_add(boost_bps)
+        .ok_or(MathError::Overflow)?;
+    mul_div(amount, BPS_DENOMINATOR, scale)
+}
+
+// A year's reward on `amount` at an APR of `rate_bps`, e.g. 1250 is 12.5%.
+pub(crate) fn annual_reward(amount: u64, rate_bps: u64) -> Result<u64, MathError> {
+    bps_of(amount, rate_bps)
+}
+
+// A year's worth of `reward` paid every `reward_period` seconds. Periods longer than a year
+// give less than one `reward`, rather than dividing by zero.
+pub(crate) fn annualize(reward: u64, reward_period: u64) -> Result<u64, MathError> {
+    mul_div(reward, YEAR, reward_period)
+}
+
+// Reward earned over `elapsed` seconds at `reward` every `per` seconds. Unless `linear`, only
+// whole reward periods count, so time since the last boundary earns nothing yet.
+pub(crate) fn accrued_reward(
+    reward: u64,
+    per: u64,
+    elapsed: u64,
+    reward_period: u64,
+    linear: bool,
+) -> Result<u64, MathError> {
+    let counted = if linear {
+        elapsed
+    } else {
+        let partial = elapsed
+            .checked_rem(reward_period)
+            .ok_or(MathError::DivideByZero)?;
+        elapsed - partial
+    };
+
+    mul_div(reward, counted, per)
+}
+
+// Converts a deposit into shares of a pool holding `total_assets` against `total_shares`.
+pub(crate) fn shares_for_amount(
+    amount: u64,
+    total_shares: u64,
+    total_assets: u64,
+) -> Result<u64, MathError> {
+    if total_shares == 0 || total_assets == 0 {
+        return Ok(amount);
+    }
+
+    mul_div(amount, total_shares, total_assets)
+}
+
+// Converts pool shares back into the amount of stake token they are worth.
+pub(crate) fn amount_for_shares(
+    shares: u64,
+    total_shares: u64,
+    total_assets: u64,
+) -> Result<u64, MathError> {
+    if total_shares == 0 {
+        return Ok(0);
+    }
+
+    mul_div(shares, total_assets, total_shares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_overflow() {
+        assert_eq!(mul_div(u64::MAX, 2, 1), Err(MathError::Overflow));
+        // the product may exceed u64 as long as the result fits
+        assert_eq!(mul_div(u64::MAX, 2, 2), Ok(u64::MAX));
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX), Ok(u64::MAX));
+        assert_eq!(mul_div(1, 1, 0), Err(MathError::DivideByZero));
+        assert_eq!(mul_div(7, 3, 2), Ok(10));
+    }
+
+    #[test]
+    fn zero_reward_period() {
+        assert_eq!(annualize(100, 0), Err(MathError::DivideByZero));
+        assert_eq!(
+            accrued_reward(100, YEAR, 1_000, 0, false),
+            Err(MathError::DivideByZero)
+        );
+        // linear accrual never counts periods
+        assert_eq!(accrued_reward(100, 1_000, 500, 0, true), Ok(50));
+    }
+
+    #[test]
+    fn reward_period_longer_than_a_year() {
+        assert_eq!(annualize(1_000, 2 * YEAR), Ok(500));
+        assert_eq!(annualize(1, 2 * YEAR), Ok(0));
+
+        // a year in whole periods counts none of a longer period
+        assert_eq!(accrued_reward(1_000, YEAR, YEAR, 2 * YEAR, false), Ok(0));
+        assert_eq!(accrued_reward(1_000, YEAR, YEAR, 2 * YEAR, true), Ok(1_000));
+    }
+
+    #[test]
+    fn whole_periods() {
+        assert_eq!(accrued_reward(1_000, 100, 99, 10, false), Ok(900));
+        assert_eq!(accrued_reward(1_000, 100, 99, 10, true), Ok(990));
+        assert_eq!(accrued_reward(1_000, 100, 9, 10, false), Ok(0));
+    }
+
+    #[test]
+    fn annual_reward_in_bps() {
+        assert_eq!(annual_reward(1_000, 1_250), Ok(125));
+        assert_eq!(annual_reward(1_000, 20_000), Ok(2_000));
+        assert_eq!(annual_reward(u64::MAX, 20_000), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn fees() {
+        assert_eq!(deduct_fee(1_000, 500), Ok(950));
+        assert_eq!(deduct_fee(1_000, 0), Ok(1_000));
+        assert_eq!(deduct_fee(1_000, BPS_DENOMINATOR), Ok(0));
+        // the fee rounds down, in the staker's favour
+        assert_eq!(deduct_fee(19, 500), Ok(19));
+        assert_eq!(deduct_fee(u64::MAX, 500), Ok(u64::MAX - u64::MAX / 20));
+        // a fee over the whole amount can't be taken
+        assert_eq!(
+            deduct_fee(1_000, 2 * BPS_DENOMINATOR),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn boost_round_trip() {
+        for boost_bps in [0, 1, 2_500, 10_000, 33_333] {
+            for amount in [0, 1, 999, 1_000_000, u64::MAX / 8] {
+                let boosted = apply_boost(amount, boost_bps).unwrap();
+                assert!(boosted >= amount);
+                // rounding down on the way back never pays out more than the base reward
+                let base = remove_boost(boosted, boost_bps).unwrap();
+                assert!(base <= amount);
+                assert!(amount - base <= 1);
+            }
+        }
+
+        assert_eq!(apply_boost(1_000, 2_500), Ok(1_250));
+        assert_eq!(remove_boost(1_250, 2_500), Ok(1_000));
+        assert_eq!(apply_boost(u64::MAX, 1), Err(MathError::Overflow));
+        assert_eq!(apply_boost(1, u64::MAX), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn empty_pool() {
+        // the first deposit sets the exchange rate at one share per token
+        assert_eq!(shares_for_amount(500, 0, 0), Ok(500));
+        // shares left over a drained pool, or assets over no shares
+        assert_eq!(shares_for_amount(500, 100, 0), Ok(500));
+        assert_eq!(shares_for_amount(500, 0, 100), Ok(500));
+
+        assert_eq!(amount_for_shares(500, 0, 0), Ok(0));
+        assert_eq!(amount_for_shares(500, 0, 100), Ok(0));
+        assert_eq!(amount_for_shares(0, 100, 1_000), Ok(0));
+    }
+
+    #[test]
+    fn share_round_trip() {
+        // a pool that has grown 10% since it opened
+        let (total_shares, total_assets) = (1_000, 1_100);
+
+        let shares = shares_for_amount(550, total_shares, total_assets).unwrap();
+        assert_eq!(shares, 500);
+        assert_eq!(
+            amount_for_shares(shares, total_shares, total_assets),
+            Ok(550)
+        );
+
+        // deposits round down in the pool's favour
+        assert_eq!(shares_for_amount(1, total_shares, total_assets), Ok(0));
+        assert_eq!(amount_for_shares(1, total_shares, total_assets), Ok(1));
+        assert_eq!(
+            amount_for_shares(u64::MAX, 1, u64::MAX),
+            Err(MathError::Overflow)
+        );
+    }
+}
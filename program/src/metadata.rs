@@ -0,0 +1,112 @@
+// Position NFT metadata. Wallets and marketplaces show the on-chain name and symbol without
+// fetching anything, so the name carries the position's stake, APR and unlock date, e.g.
+// "1.2K @ 12.5% to 2026-10-18". Names are capped at 32 bytes, so the stake is abbreviated; the
+// exact figures stay on the stake account.
+use spl_token_metadata::state::MAX_NAME_LENGTH;
+
+const SUFFIXES: [&str; 6] = ["", "K", "M", "B", "T", "Q"];
+
+pub(crate) fn position_name(amount: u64, decimals: u8, rate_bps: u64, unlock: u64) -> String {
+    let mut name = format!(
+        "{} @ {}% to {}",
+        compact_amount(amount, decimals),
+        percent(rate_bps),
+        date(unlock)
+    );
+    name.truncate(MAX_NAME_LENGTH);
+    name
+}
+
+// `amount` base units in whole tokens to one decimal place, with a thousands suffix.
+fn compact_amount(amount: u64, decimals: u8) -> String {
+    let one = 10u128.checked_pow(decimals as u32).unwrap_or(u128::MAX);
+
+    let mut unit = one;
+    let mut suffix = SUFFIXES[0];
+    for next in &SUFFIXES[1..] {
+        match unit.checked_mul(1_000) {
+            Some(x) if amount as u128 >= x => {
+                unit = x;
+                suffix = next;
+            }
+            _ => break,
+        }
+    }
+
+    let tenths = amount as u128 * 10 / unit;
+    match tenths % 10 {
+        0 => format!("{}{}", tenths / 10, suffix),
+        x => format!("{}.{}{}", tenths / 10, x, suffix),
+    }
+}
+
+// Basis points as a percentage without trailing zeros.
+fn percent(bps: u64) -> String {
+    match bps % 100 {
+        0 => format!("{}", bps / 100),
+        x if x % 10 == 0 => format!("{}.{}", bps / 100, x / 10),
+        x => format!("{}.{:02}", bps / 100, x),
+    }
+}
+
+// A unix timestamp as a UTC `YYYY-MM-DD` date.
+fn date(timestamp: u64) -> String {
+    // days since 0000-03-01, so leap days fall at the end of each year
+    let days = timestamp / 86_400 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let (month, year) = match shifted_month {
+        x if x < 10 => (x + 3, era * 400 + year_of_era),
+        x => (x - 9, era * 400 + year_of_era + 1),
+    };
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(951_868_800), "2000-03-01");
+        assert_eq!(date(1_792_281_599), "2026-10-17");
+        assert_eq!(date(1_792_281_600), "2026-10-18");
+        assert_eq!(date(4_107_542_400), "2100-03-01");
+    }
+
+    #[test]
+    fn amounts() {
+        assert_eq!(compact_amount(0, 9), "0");
+        assert_eq!(compact_amount(1_500_000_000, 9), "1.5");
+        assert_eq!(compact_amount(999_999_999_999, 9), "999.9");
+        assert_eq!(compact_amount(1_250_000_000_000, 9), "1.2K");
+        assert_eq!(compact_amount(42_000_000, 0), "42M");
+        assert_eq!(compact_amount(u64::MAX, 0), "18446.7Q");
+        assert_eq!(compact_amount(u64::MAX, 255), "0");
+    }
+
+    #[test]
+    fn percents() {
+        assert_eq!(percent(1_250), "12.5");
+        assert_eq!(percent(20_000), "200");
+        assert_eq!(percent(5), "0.05");
+        assert_eq!(percent(101), "1.01");
+    }
+
+    #[test]
+    fn names_fit() {
+        assert_eq!(
+            position_name(1_250_000_000_000, 9, 1_250, 1_792_281_600),
+            "1.2K @ 12.5% to 2026-10-18"
+        );
+        assert!(position_name(u64::MAX, 0, u64::MAX, u64::MAX).len() <= MAX_NAME_LENGTH);
+    }
+}
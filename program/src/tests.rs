@@ -0,0 +1,1674 @@
+// Program tests. `cargo test` runs them against the native processor, `cargo test-bpf` against
+// the BPF build.
+use solana_program_test::{
+    processor, BanksClientError, ProgramTest, ProgramTestBanksClientExt, ProgramTestContext,
+};
+use solana_sdk::{
+    account::Account,
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_option::COption,
+    signature::{Keypair, Signer},
+    sysvar,
+    transaction::{Transaction, TransactionError},
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+use super::*;
+
+fn program_test() -> ProgramTest {
+    ProgramTest::new("staking", id(), processor!(process_instruction))
+}
+
+// Adds an account owned by the program holding `data`, rent exempt at its size.
+fn add_program_account(program_test: &mut ProgramTest, address: Pubkey, data: Vec<u8>) {
+    program_test.add_account(
+        address,
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+}
+
+async fn transaction(
+    context: &mut ProgramTestContext,
+    instruction: StakeInstruction,
+    accounts: Vec<AccountMeta>,
+    signers: &[&Keypair],
+) -> Transaction {
+    // a fresh blockhash keeps a repeated instruction from being deduplicated
+    context.last_blockhash = context
+        .banks_client
+        .get_new_latest_blockhash(&context.last_blockhash)
+        .await
+        .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[Instruction::new_with_bytes(
+            id(),
+            &instruction.try_to_vec().unwrap(),
+            accounts,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend(signers);
+    transaction.sign(&all_signers, context.last_blockhash);
+    transaction
+}
+
+async fn process(
+    context: &mut ProgramTestContext,
+    instruction: StakeInstruction,
+    accounts: Vec<AccountMeta>,
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let transaction = transaction(context, instruction, accounts, signers).await;
+    context.banks_client.process_transaction(transaction).await
+}
+
+fn instruction_error(result: Result<(), BanksClientError>) -> InstructionError {
+    match result.map_err(|err| err.unwrap()) {
+        Err(TransactionError::InstructionError(_, err)) => err,
+        other => panic!("expected an instruction error, got {:?}", other),
+    }
+}
+
+fn custom_error(result: Result<(), BanksClientError>) -> u32 {
+    match instruction_error(result) {
+        InstructionError::Custom(code) => code,
+        other => panic!("expected a custom error, got {:?}", other),
+    }
+}
+
+async fn account_data(context: &mut ProgramTestContext, address: Pubkey) -> Vec<u8> {
+    context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap()
+        .data
+}
+
+async fn migrate(
+    context: &mut ProgramTestContext,
+    address: Pubkey,
+) -> Result<(), BanksClientError> {
+    let accounts = vec![
+        AccountMeta::new(context.payer.pubkey(), true),
+        AccountMeta::new(address, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    process(context, StakeInstruction::MigrateAccount, accounts, &[]).await
+}
+
+fn vault_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED], &id())
+}
+
+fn stake_address(staker: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[&staker.to_bytes()], &id())
+}
+
+// A legacy stake account as allocated: its 105 Borsh bytes and the unused byte after them.
+fn legacy_stake_account(legacy: &LegacyStakeData) -> Vec<u8> {
+    let mut data = legacy.try_to_vec().unwrap();
+    data.push(0);
+    data
+}
+
+fn legacy_stake(staker: Pubkey) -> LegacyStakeData {
+    LegacyStakeData {
+        timestamp: 1_650_000_000,
+        staker,
+        mint: MINT,
+        active: true,
+        withdrawn: 0,
+        harvested: 7,
+        staked_amount: 1_000,
+        max_reward: 2_000,
+    }
+}
+
+#[test]
+fn rejects_malformed_instruction_data() {
+    let trailing = |instruction: StakeInstruction| {
+        let mut data = instruction.try_to_vec().unwrap();
+        data.push(0);
+        data
+    };
+
+    for data in [
+        trailing(StakeInstruction::Unstake),
+        trailing(StakeInstruction::Withdraw { amount: TOKEN }),
+        trailing(StakeInstruction::Stake {
+            amount: TOKEN,
+            referrer: None,
+        }),
+        // an unknown tag
+        vec![u8::MAX],
+        vec![],
+    ] {
+        assert_eq!(
+            process_instruction(&id(), &[], &data),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+}
+
+#[tokio::test]
+async fn migrate_legacy_stake() {
+    let staker = Pubkey::new_unique();
+    let (address, bump) = stake_address(&staker);
+    let legacy = legacy_stake(staker);
+
+    let mut program_test = program_test();
+    let data = legacy_stake_account(&legacy);
+    assert_eq!(data.len() as u64, LEGACY_STAKE_SIZE);
+    add_program_account(&mut program_test, address, data);
+    let mut context = program_test.start_with_context().await;
+
+    migrate(&mut context, address).await.unwrap();
+
+    let data = account_data(&mut context, address).await;
+    assert_eq!(data.len() as u64, STAKE_SIZE);
+    let stake_data: StakeData = bytemuck::pod_read_unaligned(&data);
+    assert_eq!(
+        stake_data,
+        StakeData {
+            key: AccountKey::StakeV1 as u8,
+            active: 1,
+            bump,
+            padding: [0; 5],
+            last_accrual: legacy.timestamp,
+            withdrawn: legacy.withdrawn,
+            harvested: legacy.harvested,
+            staked_amount: legacy.staked_amount,
+            max_reward: legacy.max_reward,
+            shares: 0,
+            boost_bps: 0,
+            staker,
+            mint: MINT,
+            position_mint: Pubkey::default(),
+            referrer: Pubkey::default(),
+            rate: 0,
+            maturity: 0,
+            lock_start: 0,
+            unlock_at: 0,
+            pending_reward: 0,
+            reserved: [0; 24],
+        }
+    );
+
+    // the migrated account is on the current layout
+    assert_eq!(custom_error(migrate(&mut context, address).await), 0x114);
+}
+
+#[tokio::test]
+async fn migrate_legacy_stake_at_wrong_address() {
+    let address = Pubkey::new_unique();
+
+    let mut program_test = program_test();
+    let data = legacy_stake_account(&legacy_stake(Pubkey::new_unique()));
+    add_program_account(&mut program_test, address, data);
+    let mut context = program_test.start_with_context().await;
+
+    assert_eq!(custom_error(migrate(&mut context, address).await), 0x60);
+}
+
+#[tokio::test]
+async fn migrate_legacy_vault() {
+    let (address, bump) = vault_address();
+    let legacy = LegacyVaultData {
+        mint: MINT,
+        min_period: 86_400,
+        reward_period: 3_600,
+        rate: 2,
+        early_withdrawal_fee: 20,
+        total_obligations: 4_000,
+        total_staked: 2_000,
+    };
+
+    let mut program_test = program_test();
+    let data = legacy.try_to_vec().unwrap();
+    assert_eq!(data.len() as u64, LEGACY_VAULT_SIZE);
+    add_program_account(&mut program_test, address, data);
+    let mut context = program_test.start_with_context().await;
+    let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+
+    migrate(&mut context, address).await.unwrap();
+
+    let data = account_data(&mut context, address).await;
+    assert_eq!(data.len() as u64, VAULT_SIZE);
+    let vault_data: VaultData = bytemuck::pod_read_unaligned(&data);
+    assert_eq!(
+        vault_data,
+        VaultData {
+            key: AccountKey::VaultV1 as u8,
+            position_nft: 0,
+            share_accounting: 0,
+            bump,
+            emergency: 0,
+            paused: 0,
+            linear_accrual: 0,
+            lock_policy: LOCK_EXTEND,
+            min_period: legacy.min_period,
+            reward_period: legacy.reward_period,
+            // a 2x multiplier is 200% APR
+            rate: 20_000,
+            early_withdrawal_fee: DEFAULT_EARLY_EXIT_FEE_BPS,
+            total_obligations: legacy.total_obligations,
+            total_staked: legacy.total_staked,
+            total_shares: 0,
+            last_accrual: clock.unix_timestamp as u64,
+            boost_bps: 0,
+            referral_bps: 0,
+            mint: MINT,
+            receipt_mint: Pubkey::default(),
+            collection: Pubkey::default(),
+            boost_collection: Pubkey::default(),
+            multisig: Pubkey::default(),
+            rollover_policy: ROLLOVER_COMPOUND,
+            padding: [0; 7],
+            pool_reserve: 0,
+            reserved: [0; 16],
+        }
+    );
+
+    assert_eq!(custom_error(migrate(&mut context, address).await), 0x114);
+}
+
+#[tokio::test]
+async fn migrate_legacy_vault_at_wrong_address() {
+    let address = Pubkey::new_unique();
+
+    let mut program_test = program_test();
+    add_program_account(
+        &mut program_test,
+        address,
+        vec![0; LEGACY_VAULT_SIZE as usize],
+    );
+    let mut context = program_test.start_with_context().await;
+
+    assert_eq!(custom_error(migrate(&mut context, address).await), 0x61);
+}
+
+// A fungible vault funded with `vault_balance`, a staker holding `STAKER_BALANCE` and a referrer
+// owed `referral_pending`. `ADMIN_PK` has no keypair in tests, so the vault is administered by a
+// one-signer multisig.
+struct Fixture {
+    context: ProgramTestContext,
+    admin: Keypair,
+    staker: Keypair,
+    referrer: Keypair,
+}
+
+const TOKEN: u64 = 1_000_000_000;
+const STAKER_BALANCE: u64 = 10_000 * TOKEN;
+const DAY: i64 = 86_400;
+
+fn multisig_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MULTISIG_SEED, &vault_address().0.to_bytes()], &id())
+}
+
+fn referral_address(referrer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[REFERRAL_SEED, &referrer.to_bytes()], &id()).0
+}
+
+fn token_address(owner: &Pubkey) -> Pubkey {
+    spl_associated_token_account::get_associated_token_address(owner, &MINT)
+}
+
+fn token_account(owner: &Pubkey, amount: u64) -> Account {
+    mint_token_account(&MINT, owner, amount)
+}
+
+fn mint_token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(
+        TokenAccount {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        },
+        &mut data,
+    )
+    .unwrap();
+
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+// 12.5% APR, a day's lock, hourly reward periods and a 10% referral share.
+fn vault_data() -> VaultData {
+    let (_, bump) = vault_address();
+    VaultData {
+        key: AccountKey::VaultV1 as u8,
+        bump,
+        lock_policy: LOCK_EXTEND,
+        min_period: DAY as u64,
+        reward_period: 3_600,
+        rate: 1_250,
+        early_withdrawal_fee: DEFAULT_EARLY_EXIT_FEE_BPS,
+        referral_bps: 1_000,
+        mint: MINT,
+        multisig: multisig_address().0,
+        rollover_policy: ROLLOVER_COMPOUND,
+        ..VaultData::zeroed()
+    }
+}
+
+impl Fixture {
+    async fn start(vault_data: VaultData, vault_balance: u64, referral_pending: u64) -> Self {
+        let vault_data = bytemuck::bytes_of(&vault_data).to_vec();
+        Fixture::launch(program_test(), vault_data, vault_balance, referral_pending).await
+    }
+
+    // Starts `program_test` with the vault account holding `vault_data`, in whichever layout the
+    // program under test reads.
+    async fn launch(
+        mut program_test: ProgramTest,
+        vault_data: Vec<u8>,
+        vault_balance: u64,
+        referral_pending: u64,
+    ) -> Self {
+        let admin = Keypair::new();
+        let staker = Keypair::new();
+        let referrer = Keypair::new();
+        let (vault, _) = vault_address();
+        let (multisig, multisig_bump) = multisig_address();
+
+        let mut mint = vec![0; Mint::LEN];
+        Mint::pack(
+            Mint {
+                mint_authority: COption::None,
+                supply: STAKER_BALANCE + vault_balance,
+                decimals: DECIMALS,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            },
+            &mut mint,
+        )
+        .unwrap();
+        program_test.add_account(
+            MINT,
+            Account {
+                lamports: Rent::default().minimum_balance(mint.len()),
+                data: mint,
+                owner: spl_token::id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+
+        add_program_account(&mut program_test, vault, vault_data);
+        program_test.add_account(token_address(&vault), token_account(&vault, vault_balance));
+
+        let mut signers = [Pubkey::default(); MAX_SIGNERS];
+        signers[0] = admin.pubkey();
+        let multisig_data = MultisigData {
+            key: AccountKey::MultisigV1 as u8,
+            threshold: 1,
+            signer_count: 1,
+            bump: multisig_bump,
+            vault,
+            signers,
+            ..MultisigData::zeroed()
+        };
+        add_program_account(
+            &mut program_test,
+            multisig,
+            bytemuck::bytes_of(&multisig_data).to_vec(),
+        );
+
+        if referral_pending != 0 {
+            let (address, bump) = Pubkey::find_program_address(
+                &[REFERRAL_SEED, &referrer.pubkey().to_bytes()],
+                &id(),
+            );
+            let referral_data = ReferralData {
+                key: AccountKey::ReferralV1 as u8,
+                bump,
+                pending: referral_pending,
+                total_earned: referral_pending,
+                referrer: referrer.pubkey(),
+                ..ReferralData::zeroed()
+            };
+            add_program_account(
+                &mut program_test,
+                address,
+                bytemuck::bytes_of(&referral_data).to_vec(),
+            );
+        }
+
+        for (owner, amount) in [
+            (admin.pubkey(), 0),
+            (staker.pubkey(), STAKER_BALANCE),
+            (referrer.pubkey(), 0),
+        ] {
+            program_test.add_account(token_address(&owner), token_account(&owner, amount));
+        }
+
+        Fixture {
+            context: program_test.start_with_context().await,
+            admin,
+            staker,
+            referrer,
+        }
+    }
+
+    async fn stake(&mut self, amount: u64) -> Transaction {
+        let accounts = self.stake_accounts();
+        let instruction = StakeInstruction::Stake {
+            amount,
+            referrer: Some(self.referrer.pubkey()),
+        };
+        transaction(&mut self.context, instruction, accounts, &[&self.staker]).await
+    }
+
+    fn stake_accounts(&self) -> Vec<AccountMeta> {
+        let staker = self.staker.pubkey();
+        let (vault, _) = vault_address();
+        vec![
+            AccountMeta::new(staker, true),
+            AccountMeta::new(self.context.payer.pubkey(), true),
+            AccountMeta::new(stake_address(&staker).0, false),
+            AccountMeta::new(token_address(&staker), false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new(referral_address(&self.referrer.pubkey()), false),
+        ]
+    }
+
+    async fn unstake(&mut self) -> Transaction {
+        let accounts = self.unstake_accounts();
+        let instruction = StakeInstruction::Unstake;
+        transaction(&mut self.context, instruction, accounts, &[&self.staker]).await
+    }
+
+    fn unstake_accounts(&self) -> Vec<AccountMeta> {
+        let staker = self.staker.pubkey();
+        let (vault, _) = vault_address();
+        vec![
+            AccountMeta::new(staker, true),
+            AccountMeta::new(stake_address(&staker).0, false),
+            AccountMeta::new(token_address(&staker), false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(referral_address(&self.referrer.pubkey()), false),
+        ]
+    }
+
+    async fn withdraw(&mut self, amount: u64) -> Transaction {
+        let accounts = self.withdraw_accounts();
+        let instruction = StakeInstruction::Withdraw { amount };
+        transaction(&mut self.context, instruction, accounts, &[&self.admin]).await
+    }
+
+    fn withdraw_accounts(&self) -> Vec<AccountMeta> {
+        let (vault, _) = vault_address();
+        vec![
+            AccountMeta::new(self.admin.pubkey(), true),
+            AccountMeta::new(token_address(&self.admin.pubkey()), false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(multisig_address().0, false),
+        ]
+    }
+
+    async fn claim_referral(&mut self) -> Transaction {
+        let accounts = self.claim_referral_accounts();
+        let instruction = StakeInstruction::ClaimReferral;
+        transaction(&mut self.context, instruction, accounts, &[&self.referrer]).await
+    }
+
+    fn claim_referral_accounts(&self) -> Vec<AccountMeta> {
+        let referrer = self.referrer.pubkey();
+        let (vault, _) = vault_address();
+        vec![
+            AccountMeta::new(referrer, true),
+            AccountMeta::new(referral_address(&referrer), false),
+            AccountMeta::new(token_address(&referrer), false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ]
+    }
+
+    // Copies the account at `address` to a new address under `owner`, returning the copy.
+    async fn spoof(&mut self, address: Pubkey, owner: Pubkey) -> Pubkey {
+        let mut account = self
+            .context
+            .banks_client
+            .get_account(address)
+            .await
+            .unwrap()
+            .unwrap();
+        account.owner = owner;
+        let spoofed = Pubkey::new_unique();
+        self.context.set_account(&spoofed, &account.into());
+        spoofed
+    }
+
+    // Spoofs the vault along with a token account for it, as an attacker could create one.
+    async fn spoof_vault(&mut self, owner: Pubkey) -> Pubkey {
+        let spoofed = self.spoof(vault_address().0, owner).await;
+        self.context.set_account(
+            &token_address(&spoofed),
+            &token_account(&spoofed, 1_000 * TOKEN).into(),
+        );
+        spoofed
+    }
+
+    // Replaces the nft vault with `vault_data`, under a multisig of the admin alone, returning
+    // the vault and multisig addresses.
+    fn set_nft_vault(&mut self, vault_data: VaultData) -> (Pubkey, Pubkey) {
+        let (vault, bump) = Pubkey::find_program_address(&[NFT_VAULT_SEED], &id());
+        let (multisig, multisig_bump) =
+            Pubkey::find_program_address(&[MULTISIG_SEED, &vault.to_bytes()], &id());
+
+        let vault_data = VaultData {
+            bump,
+            multisig,
+            ..vault_data
+        };
+        let mut signers = [Pubkey::default(); MAX_SIGNERS];
+        signers[0] = self.admin.pubkey();
+        let multisig_data = MultisigData {
+            key: AccountKey::MultisigV1 as u8,
+            threshold: 1,
+            signer_count: 1,
+            bump: multisig_bump,
+            vault,
+            signers,
+            ..MultisigData::zeroed()
+        };
+        for (address, data) in [
+            (vault, bytemuck::bytes_of(&vault_data).to_vec()),
+            (multisig, bytemuck::bytes_of(&multisig_data).to_vec()),
+        ] {
+            self.context.set_account(
+                &address,
+                &Account {
+                    lamports: Rent::default().minimum_balance(data.len()),
+                    data,
+                    owner: id(),
+                    executable: false,
+                    rent_epoch: 0,
+                }
+                .into(),
+            );
+        }
+
+        (vault, multisig)
+    }
+
+    // Replaces the vault's multisig with `signers` and `threshold`.
+    fn set_multisig(&mut self, signers: &[Pubkey], threshold: u8) {
+        let (multisig, bump) = multisig_address();
+        let mut padded = [Pubkey::default(); MAX_SIGNERS];
+        padded[..signers.len()].copy_from_slice(signers);
+        let multisig_data = MultisigData {
+            key: AccountKey::MultisigV1 as u8,
+            threshold,
+            signer_count: signers.len() as u8,
+            bump,
+            vault: vault_address().0,
+            signers: padded,
+            ..MultisigData::zeroed()
+        };
+        let data = bytemuck::bytes_of(&multisig_data).to_vec();
+        self.context.set_account(
+            &multisig,
+            &Account {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: id(),
+                executable: false,
+                rent_epoch: 0,
+            }
+            .into(),
+        );
+    }
+
+    async fn process(&mut self, transaction: Transaction) -> Result<(), BanksClientError> {
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    // Processes `transaction`, returning the compute units it used.
+    async fn metered(&mut self, transaction: Transaction) -> u64 {
+        let simulation = self
+            .context
+            .banks_client
+            .simulate_transaction(transaction.clone())
+            .await
+            .unwrap();
+        self.process(transaction).await.unwrap();
+        simulation.simulation_details.unwrap().units_consumed
+    }
+
+    // Sets the vault's token balance, as if funds had left it without the program's checks.
+    fn set_vault_balance(&mut self, amount: u64) {
+        let (vault, _) = vault_address();
+        self.context.set_account(
+            &token_address(&vault),
+            &token_account(&vault, amount).into(),
+        );
+    }
+
+    async fn warp(&mut self, seconds: i64) {
+        let mut clock: Clock = self.context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp += seconds;
+        self.context.set_sysvar(&clock);
+    }
+}
+
+// The program as of the commit before PDA bumps were stored, for the benchmark's baseline. Its
+// `.so` is loaded from `tests/fixtures`, see the README.
+fn baseline_program_test() -> ProgramTest {
+    let mut program_test = ProgramTest::default();
+    program_test.add_program("staking_baseline", id(), None);
+    program_test
+}
+
+// The baseline's Borsh vault layout, keyed `VaultV1` (2), with the fields `vault_data()` sets. Its
+// `rate` is a whole multiple of the stake paid per year, and positions always reserved 2x.
+fn baseline_vault_data() -> Vec<u8> {
+    let vault = (
+        (2u8, MINT, DAY as u64, 3_600u64, 2u64),
+        (0u64, 0u64, 0u64, false, Pubkey::default(), false),
+        (0u64, 0u64, Pubkey::default(), Pubkey::default(), 0u64),
+        (1_000u64, [0u8; 64]),
+    );
+    vault.try_to_vec().unwrap()
+}
+
+impl Fixture {
+    // Meters the instructions a position goes through, from opening to its referrer's claim.
+    async fn meter_position(&mut self) -> Vec<(&'static str, u64)> {
+        let mut report = vec![];
+
+        let transaction = self.stake(1_000 * TOKEN).await;
+        report.push(("Stake (open)", self.metered(transaction).await));
+
+        self.warp(30 * DAY).await;
+        let transaction = self.stake(500 * TOKEN).await;
+        report.push(("Stake (rollover)", self.metered(transaction).await));
+
+        self.warp(30 * DAY).await;
+        let transaction = self.unstake().await;
+        report.push(("Unstake", self.metered(transaction).await));
+
+        let transaction = self.claim_referral().await;
+        report.push(("ClaimReferral", self.metered(transaction).await));
+
+        report
+    }
+}
+
+// Compute units per instruction, before and after PDA bumps were stored. Both columns come from
+// BPF builds, the baseline from `tests/fixtures/staking_baseline.so`, so run
+//     cargo test-bpf -- --ignored --nocapture compute_units
+// `Withdraw` needs `ADMIN_PK` to sign on the baseline, which has no multisig, so it only has a
+// current figure.
+#[tokio::test]
+#[ignore]
+async fn compute_units() {
+    let mut baseline = Fixture::launch(
+        baseline_program_test(),
+        baseline_vault_data(),
+        10_000 * TOKEN,
+        0,
+    )
+    .await;
+    let before = baseline.meter_position().await;
+
+    let mut fixture = Fixture::start(vault_data(), 1_000 * TOKEN, 0).await;
+    let mut after = fixture.meter_position().await;
+    let transaction = fixture.withdraw(TOKEN).await;
+    after.push(("Withdraw", fixture.metered(transaction).await));
+
+    println!("{:<20}{:>10}{:>10}", "", "baseline", "current");
+    for (index, (instruction, units)) in after.into_iter().enumerate() {
+        let baseline = before
+            .get(index)
+            .map_or("-".to_string(), |(_, units)| units.to_string());
+        println!("{:<20}{:>10}{:>10}", instruction, baseline, units);
+    }
+}
+
+#[tokio::test]
+async fn withdraw_over_surplus() {
+    let mut fixture = Fixture::start(vault_data(), 200 * TOKEN, 0).await;
+    let transaction = fixture.stake(1_000 * TOKEN).await;
+    fixture.process(transaction).await.unwrap();
+
+    // 1_200 held against 1_000 staked and 125 reserved for the position's year
+    let transaction = fixture.withdraw(75 * TOKEN + 1).await;
+    assert_eq!(custom_error(fixture.process(transaction).await), 0x115);
+
+    let transaction = fixture.withdraw(75 * TOKEN).await;
+    fixture.process(transaction).await.unwrap();
+
+    let transaction = fixture.withdraw(1).await;
+    assert_eq!(custom_error(fixture.process(transaction).await), 0x115);
+}
+
+#[tokio::test]
+async fn unstake_underfunded() {
+    let mut fixture = Fixture::start(vault_data(), 200 * TOKEN, 0).await;
+    let transaction = fixture.stake(1_000 * TOKEN).await;
+    fixture.process(transaction).await.unwrap();
+    fixture.warp(30 * DAY).await;
+
+    // the vault can't cover the principal, let alone the reward
+    fixture.set_vault_balance(500 * TOKEN);
+    let transaction = fixture.unstake().await;
+    assert_eq!(custom_error(fixture.process(transaction).await), 0x115);
+
+    // nor the reward on top of the principal
+    fixture.set_vault_balance(1_000 * TOKEN);
+    let transaction = fixture.unstake().await;
+    assert_eq!(custom_error(fixture.process(transaction).await), 0x115);
+
+    fixture.set_vault_balance(1_200 * TOKEN);
+    let transaction = fixture.unstake().await;
+    fixture.process(transaction).await.unwrap();
+}
+
+#[tokio::test]
+async fn claim_referral_over_credited() {
+    // a referrer credited 100 while the vault only holds 50 beyond the staked principal
+    let vault_data = VaultData {
+        total_staked: 1_000 * TOKEN,
+        total_obligations: 100 * TOKEN,
+        ..vault_data()
+    };
+    let mut fixture = Fixture::start(vault_data, 1_050 * TOKEN, 100 * TOKEN).await;
+
+    let transaction = fixture.claim_referral().await;
+    assert_eq!(custom_error(fixture.process(transaction).await), 0x115);
+
+    fixture.set_vault_balance(1_100 * TOKEN);
+    let transaction = fixture.claim_referral().await;
+    fixture.process(transaction).await.unwrap();
+}
+
+#[tokio::test]
+async fn receipt_holder_redeems_position() {
+    let (vault, _) = vault_address();
+    let (receipt_mint, _) = Pubkey::find_program_address(&[RECEIPT_SEED], &id());
+    let vault_data = VaultData {
+        receipt_mint,
+        ..vault_data()
+    };
+    let mut fixture = Fixture::start(vault_data, 1_000 * TOKEN, 0).await;
+    let staker = fixture.staker.pubkey();
+    let lender = Keypair::new();
+
+    let mut mint = vec![0; Mint::LEN];
+    Mint::pack(
+        Mint {
+            mint_authority: COption::Some(vault),
+            supply: 0,
+            decimals: DECIMALS,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        },
+        &mut mint,
+    )
+    .unwrap();
+    fixture.context.set_account(
+        &receipt_mint,
+        &Account {
+            lamports: Rent::default().minimum_balance(mint.len()),
+            data: mint,
+            owner: spl_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+        .into(),
+    );
+    fixture.context.set_account(
+        &token_address(&lender.pubkey()),
+        &token_account(&lender.pubkey(), 0).into(),
+    );
+
+    let staker_receipts =
+        spl_associated_token_account::get_associated_token_address(&staker, &receipt_mint);
+    let lender_receipts =
+        spl_associated_token_account::get_associated_token_address(&lender.pubkey(), &receipt_mint);
+
+    let mut accounts = vec![
+        AccountMeta::new(staker, true),
+        AccountMeta::new(fixture.context.payer.pubkey(), true),
+        AccountMeta::new(stake_address(&staker).0, false),
+        AccountMeta::new(token_address(&staker), false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new(token_address(&vault), false),
+        AccountMeta::new_readonly(MINT, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(receipt_mint, false),
+        AccountMeta::new(staker_receipts, false),
+    ];
+    let instruction = StakeInstruction::Stake {
+        amount: 1_000 * TOKEN,
+        referrer: None,
+    };
+    let stake = transaction(
+        &mut fixture.context,
+        instruction,
+        accounts.clone(),
+        &[&fixture.staker],
+    )
+    .await;
+    fixture.process(stake).await.unwrap();
+
+    // the staker lends its receipts out, as if transferred to the lender
+    let shares = TokenAccount::unpack(&account_data(&mut fixture.context, staker_receipts).await)
+        .unwrap()
+        .amount;
+    assert_ne!(shares, 0);
+    fixture.context.set_account(
+        &staker_receipts,
+        &mint_token_account(&receipt_mint, &staker, 0).into(),
+    );
+    fixture.context.set_account(
+        &lender_receipts,
+        &mint_token_account(&receipt_mint, &lender.pubkey(), shares).into(),
+    );
+    fixture.warp(2 * DAY).await;
+
+    // the lender burns them and redeems the staker's position
+    let unstake_accounts = |signer: Pubkey, receipts: Pubkey| {
+        vec![
+            AccountMeta::new(signer, true),
+            AccountMeta::new(stake_address(&staker).0, false),
+            AccountMeta::new(token_address(&signer), false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(receipt_mint, false),
+            AccountMeta::new(receipts, false),
+        ]
+    };
+    accounts = unstake_accounts(lender.pubkey(), lender_receipts);
+    let redeem = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&lender],
+    )
+    .await;
+    fixture.process(redeem).await.unwrap();
+
+    let redeemed = TokenAccount::unpack(
+        &account_data(&mut fixture.context, token_address(&lender.pubkey())).await,
+    )
+    .unwrap()
+    .amount;
+    assert!(redeemed >= 1_000 * TOKEN);
+    let receipts = TokenAccount::unpack(&account_data(&mut fixture.context, lender_receipts).await)
+        .unwrap()
+        .amount;
+    assert_eq!(receipts, 0);
+
+    // which leaves nothing for the staker to unstake
+    accounts = unstake_accounts(staker, staker_receipts);
+    let unstake = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(unstake).await), 0x107);
+}
+
+#[tokio::test]
+async fn share_vault_referral_claim() {
+    let vault_data = VaultData {
+        share_accounting: 1,
+        ..vault_data()
+    };
+    let mut fixture = Fixture::start(vault_data, 1_000 * TOKEN, 0).await;
+    let transaction = fixture.stake(1_000 * TOKEN).await;
+    fixture.process(transaction).await.unwrap();
+    fixture.warp(30 * DAY).await;
+
+    // the last position leaving releases the whole pool reserve, but not the referral credit
+    let transaction = fixture.unstake().await;
+    fixture.process(transaction).await.unwrap();
+    let referral: ReferralData = bytemuck::pod_read_unaligned(
+        &account_data(
+            &mut fixture.context,
+            referral_address(&fixture.referrer.pubkey()),
+        )
+        .await,
+    );
+    assert_ne!(referral.pending, 0);
+    let vault: VaultData =
+        bytemuck::pod_read_unaligned(&account_data(&mut fixture.context, vault_address().0).await);
+    assert_eq!(vault.pool_reserve, 0);
+    assert_eq!(vault.total_obligations, referral.pending);
+
+    let transaction = fixture.claim_referral().await;
+    fixture.process(transaction).await.unwrap();
+    let claimed = TokenAccount::unpack(
+        &account_data(
+            &mut fixture.context,
+            token_address(&fixture.referrer.pubkey()),
+        )
+        .await,
+    )
+    .unwrap()
+    .amount;
+    assert_eq!(claimed, referral.pending);
+}
+
+#[tokio::test]
+async fn withdraw_from_nft_vault() {
+    let mut fixture = Fixture::start(vault_data(), 0, 0).await;
+
+    // 100 backing a single staked nft's reserved reward
+    let (vault, multisig) = fixture.set_nft_vault(VaultData {
+        collection: Pubkey::new_unique(),
+        total_staked: 1,
+        total_obligations: 100 * TOKEN,
+        ..vault_data()
+    });
+    fixture.context.set_account(
+        &token_address(&vault),
+        &token_account(&vault, 150 * TOKEN).into(),
+    );
+
+    let admin = fixture.admin.pubkey();
+    let withdraw = |amount| {
+        let accounts = vec![
+            AccountMeta::new(admin, true),
+            AccountMeta::new(token_address(&admin), false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(multisig, false),
+        ];
+        (StakeInstruction::Withdraw { amount }, accounts)
+    };
+
+    let (instruction, accounts) = withdraw(50 * TOKEN + 1);
+    let over_surplus = transaction(
+        &mut fixture.context,
+        instruction,
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(over_surplus).await), 0x115);
+
+    let (instruction, accounts) = withdraw(50 * TOKEN);
+    let surplus = transaction(
+        &mut fixture.context,
+        instruction,
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    fixture.process(surplus).await.unwrap();
+}
+
+#[tokio::test]
+async fn stake_rejects_spoofed_accounts() {
+    let mut fixture = Fixture::start(vault_data(), 1_000 * TOKEN, 10 * TOKEN).await;
+    let instruction = StakeInstruction::Stake {
+        amount: 100 * TOKEN,
+        referrer: Some(fixture.referrer.pubkey()),
+    };
+
+    let vault = fixture.spoof_vault(Pubkey::new_unique()).await;
+    let mut accounts = fixture.stake_accounts();
+    accounts[4].pubkey = vault;
+    accounts[5].pubkey = token_address(&vault);
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        instruction.clone(),
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+
+    let vault = fixture.spoof_vault(id()).await;
+    let mut accounts = fixture.stake_accounts();
+    accounts[4].pubkey = vault;
+    accounts[5].pubkey = token_address(&vault);
+    let wrong_vault = transaction(
+        &mut fixture.context,
+        instruction.clone(),
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_vault).await), 0x12);
+
+    // a program account that isn't the referrer's can't stand in for it when the position opens
+    let referral = fixture
+        .spoof(referral_address(&fixture.referrer.pubkey()), id())
+        .await;
+    let mut accounts = fixture.stake_accounts();
+    accounts[11].pubkey = referral;
+    let wrong_referral = transaction(
+        &mut fixture.context,
+        instruction.clone(),
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_referral).await), 0x75);
+
+    let open = fixture.stake(100 * TOKEN).await;
+    fixture.process(open).await.unwrap();
+
+    let stake = fixture
+        .spoof(stake_address(&fixture.staker.pubkey()).0, id())
+        .await;
+    let mut accounts = fixture.stake_accounts();
+    accounts[2].pubkey = stake;
+    let wrong_stake = transaction(
+        &mut fixture.context,
+        instruction,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_stake).await), 0x10);
+}
+
+#[tokio::test]
+async fn unstake_rejects_spoofed_accounts() {
+    let mut fixture = Fixture::start(vault_data(), 1_000 * TOKEN, 0).await;
+    let open = fixture.stake(1_000 * TOKEN).await;
+    fixture.process(open).await.unwrap();
+    fixture.warp(2 * DAY).await;
+
+    let vault = fixture.spoof_vault(Pubkey::new_unique()).await;
+    let mut accounts = fixture.unstake_accounts();
+    accounts[3].pubkey = vault;
+    accounts[4].pubkey = token_address(&vault);
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+
+    let vault = fixture.spoof_vault(id()).await;
+    let mut accounts = fixture.unstake_accounts();
+    accounts[3].pubkey = vault;
+    accounts[4].pubkey = token_address(&vault);
+    let wrong_vault = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_vault).await), 0x61);
+
+    let stake_address = stake_address(&fixture.staker.pubkey()).0;
+    let stake = fixture.spoof(stake_address, Pubkey::new_unique()).await;
+    let mut accounts = fixture.unstake_accounts();
+    accounts[1].pubkey = stake;
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+
+    let stake = fixture.spoof(stake_address, id()).await;
+    let mut accounts = fixture.unstake_accounts();
+    accounts[1].pubkey = stake;
+    let wrong_stake = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_stake).await), 0x60);
+
+    let referral = fixture
+        .spoof(referral_address(&fixture.referrer.pubkey()), id())
+        .await;
+    let mut accounts = fixture.unstake_accounts();
+    accounts[7].pubkey = referral;
+    let wrong_referral = transaction(
+        &mut fixture.context,
+        StakeInstruction::Unstake,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_referral).await), 0x75);
+
+    let unstake = fixture.unstake().await;
+    fixture.process(unstake).await.unwrap();
+}
+
+#[tokio::test]
+async fn withdraw_rejects_spoofed_accounts() {
+    let mut fixture = Fixture::start(vault_data(), 1_000 * TOKEN, 0).await;
+    let instruction = StakeInstruction::Withdraw { amount: TOKEN };
+
+    let vault = fixture.spoof_vault(Pubkey::new_unique()).await;
+    let mut accounts = fixture.withdraw_accounts();
+    accounts[2].pubkey = vault;
+    accounts[3].pubkey = token_address(&vault);
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        instruction.clone(),
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+
+    let vault = fixture.spoof_vault(id()).await;
+    let mut accounts = fixture.withdraw_accounts();
+    accounts[2].pubkey = vault;
+    accounts[3].pubkey = token_address(&vault);
+    let wrong_vault = transaction(
+        &mut fixture.context,
+        instruction.clone(),
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_vault).await), 0x261);
+
+    // a copy of the multisig names the same signers but isn't the vault's
+    let multisig = fixture.spoof(multisig_address().0, id()).await;
+    let mut accounts = fixture.withdraw_accounts();
+    accounts[6].pubkey = multisig;
+    let wrong_multisig = transaction(
+        &mut fixture.context,
+        instruction.clone(),
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_multisig).await), 0x234);
+
+    let withdraw = fixture.withdraw(TOKEN).await;
+    fixture.process(withdraw).await.unwrap();
+
+    // the multisig's own address only counts while the program owns it
+    let mut account = fixture
+        .context
+        .banks_client
+        .get_account(multisig_address().0)
+        .await
+        .unwrap()
+        .unwrap();
+    account.owner = Pubkey::new_unique();
+    fixture
+        .context
+        .set_account(&multisig_address().0, &account.into());
+    let accounts = fixture.withdraw_accounts();
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        instruction,
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+}
+
+#[tokio::test]
+async fn claim_referral_rejects_spoofed_accounts() {
+    let vault_data = VaultData {
+        total_obligations: 100 * TOKEN,
+        ..vault_data()
+    };
+    let mut fixture = Fixture::start(vault_data, 1_000 * TOKEN, 100 * TOKEN).await;
+
+    let vault = fixture.spoof_vault(Pubkey::new_unique()).await;
+    let mut accounts = fixture.claim_referral_accounts();
+    accounts[3].pubkey = vault;
+    accounts[4].pubkey = token_address(&vault);
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        StakeInstruction::ClaimReferral,
+        accounts,
+        &[&fixture.referrer],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+
+    let vault = fixture.spoof_vault(id()).await;
+    let mut accounts = fixture.claim_referral_accounts();
+    accounts[3].pubkey = vault;
+    accounts[4].pubkey = token_address(&vault);
+    let wrong_vault = transaction(
+        &mut fixture.context,
+        StakeInstruction::ClaimReferral,
+        accounts,
+        &[&fixture.referrer],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_vault).await), 0x61);
+
+    let referral_address = referral_address(&fixture.referrer.pubkey());
+    let referral = fixture.spoof(referral_address, Pubkey::new_unique()).await;
+    let mut accounts = fixture.claim_referral_accounts();
+    accounts[1].pubkey = referral;
+    let wrong_owner = transaction(
+        &mut fixture.context,
+        StakeInstruction::ClaimReferral,
+        accounts,
+        &[&fixture.referrer],
+    )
+    .await;
+    assert_eq!(
+        instruction_error(fixture.process(wrong_owner).await),
+        InstructionError::IncorrectProgramId
+    );
+
+    let referral = fixture.spoof(referral_address, id()).await;
+    let mut accounts = fixture.claim_referral_accounts();
+    accounts[1].pubkey = referral;
+    let wrong_referral = transaction(
+        &mut fixture.context,
+        StakeInstruction::ClaimReferral,
+        accounts,
+        &[&fixture.referrer],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(wrong_referral).await), 0x75);
+
+    let claim = fixture.claim_referral().await;
+    fixture.process(claim).await.unwrap();
+}
+
+#[tokio::test]
+async fn nft_vault_emergency() {
+    let mut fixture = Fixture::start(vault_data(), 0, 0).await;
+    let clock: Clock = fixture.context.banks_client.get_sysvar().await.unwrap();
+    let now = clock.unix_timestamp as u64;
+
+    // a locked nft position with unstaking paused and nothing to pay its reward with
+    let (vault, multisig) = fixture.set_nft_vault(VaultData {
+        collection: Pubkey::new_unique(),
+        total_staked: 1,
+        total_obligations: 100 * TOKEN,
+        paused: PAUSE_UNSTAKE,
+        ..vault_data()
+    });
+    let staker = fixture.staker.pubkey();
+    let nft_mint = Pubkey::new_unique();
+    let (stake, stake_bump) =
+        Pubkey::find_program_address(&[NFT_VAULT_SEED, &nft_mint.to_bytes()], &id());
+    let stake_data = StakeData {
+        key: AccountKey::StakeV1 as u8,
+        bump: stake_bump,
+        active: 1,
+        last_accrual: now - DAY as u64,
+        lock_start: now - DAY as u64,
+        unlock_at: now + DAY as u64,
+        maturity: now + 364 * DAY as u64,
+        staker,
+        mint: nft_mint,
+        staked_amount: 1,
+        max_reward: 100 * TOKEN,
+        rate: 1_250,
+        ..StakeData::zeroed()
+    };
+    let nft_address = |owner: &Pubkey| {
+        spl_associated_token_account::get_associated_token_address(owner, &nft_mint)
+    };
+    for (address, account) in [
+        (
+            nft_address(&vault),
+            mint_token_account(&nft_mint, &vault, 1),
+        ),
+        (
+            nft_address(&staker),
+            mint_token_account(&nft_mint, &staker, 0),
+        ),
+        (token_address(&vault), token_account(&vault, 0)),
+        (
+            stake,
+            Account {
+                lamports: Rent::default().minimum_balance(STAKE_SIZE as usize),
+                data: bytemuck::bytes_of(&stake_data).to_vec(),
+                owner: id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        ),
+    ] {
+        fixture.context.set_account(&address, &account.into());
+    }
+
+    let admin = fixture.admin.pubkey();
+    let accounts = vec![
+        AccountMeta::new(admin, true),
+        AccountMeta::new(vault, false),
+        AccountMeta::new_readonly(multisig, false),
+    ];
+    let instruction = StakeInstruction::SetEmergency { enabled: true };
+    process(
+        &mut fixture.context,
+        instruction,
+        accounts,
+        &[&fixture.admin],
+    )
+    .await
+    .unwrap();
+
+    let other_mint = Pubkey::new_unique();
+    let accounts = vec![
+        AccountMeta::new(staker, true),
+        AccountMeta::new(fixture.context.payer.pubkey(), true),
+        AccountMeta::new(
+            Pubkey::find_program_address(&[NFT_VAULT_SEED, &other_mint.to_bytes()], &id()).0,
+            false,
+        ),
+        AccountMeta::new(
+            spl_associated_token_account::get_associated_token_address(&staker, &other_mint),
+            false,
+        ),
+        AccountMeta::new(vault, false),
+        AccountMeta::new(
+            spl_associated_token_account::get_associated_token_address(&vault, &other_mint),
+            false,
+        ),
+        AccountMeta::new(token_address(&vault), false),
+        AccountMeta::new_readonly(other_mint, false),
+        AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+    ];
+    let stake_nft = transaction(
+        &mut fixture.context,
+        StakeInstruction::StakeNft,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await;
+    assert_eq!(custom_error(fixture.process(stake_nft).await), 0x117);
+
+    let accounts = vec![
+        AccountMeta::new(staker, true),
+        AccountMeta::new(stake, false),
+        AccountMeta::new(nft_address(&staker), false),
+        AccountMeta::new(token_address(&staker), false),
+        AccountMeta::new(vault, false),
+        AccountMeta::new(nft_address(&vault), false),
+        AccountMeta::new(token_address(&vault), false),
+        AccountMeta::new_readonly(nft_mint, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+    ];
+    process(
+        &mut fixture.context,
+        StakeInstruction::UnstakeNft,
+        accounts,
+        &[&fixture.staker],
+    )
+    .await
+    .unwrap();
+
+    let balance = |data: Vec<u8>| TokenAccount::unpack(&data).unwrap().amount;
+    let returned = balance(account_data(&mut fixture.context, nft_address(&staker)).await);
+    assert_eq!(returned, 1);
+    let tokens = balance(account_data(&mut fixture.context, token_address(&staker)).await);
+    assert_eq!(tokens, STAKER_BALANCE);
+
+    let stake_data: StakeData =
+        bytemuck::pod_read_unaligned(&account_data(&mut fixture.context, stake).await);
+    assert_eq!((stake_data.active, stake_data.withdrawn), (0, 0));
+    let vault_data: VaultData =
+        bytemuck::pod_read_unaligned(&account_data(&mut fixture.context, vault).await);
+    assert_eq!(
+        (vault_data.total_staked, vault_data.total_obligations),
+        (0, 0)
+    );
+}
+
+#[tokio::test]
+async fn withdraw_needs_multisig_quorum() {
+    let mut fixture = Fixture::start(vault_data(), 200 * TOKEN, 0).await;
+    let admin = fixture.admin.pubkey();
+    let cosigner = Keypair::new();
+    let outsider = Keypair::new();
+    fixture.set_multisig(&[admin, cosigner.pubkey(), Pubkey::new_unique()], 2);
+
+    let with_cosigner = |mut accounts: Vec<AccountMeta>, cosigner: Pubkey, signed: bool| {
+        accounts.push(AccountMeta::new_readonly(cosigner, signed));
+        accounts
+    };
+    let withdraw = StakeInstruction::Withdraw { amount: TOKEN };
+
+    // below threshold
+    let accounts = fixture.withdraw_accounts();
+    let result = process(
+        &mut fixture.context,
+        withdraw.clone(),
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(result), 0x232);
+
+    // the admin signing twice is still one approval
+    let accounts = with_cosigner(fixture.withdraw_accounts(), admin, true);
+    let result = process(
+        &mut fixture.context,
+        withdraw.clone(),
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(result), 0x232);
+
+    // a signer outside the set
+    let accounts = with_cosigner(fixture.withdraw_accounts(), outsider.pubkey(), true);
+    let result = process(
+        &mut fixture.context,
+        withdraw.clone(),
+        accounts,
+        &[&fixture.admin, &outsider],
+    )
+    .await;
+    assert_eq!(custom_error(result), 0x232);
+
+    // a listed signer that didn't sign
+    let accounts = with_cosigner(fixture.withdraw_accounts(), cosigner.pubkey(), false);
+    let result = process(
+        &mut fixture.context,
+        withdraw.clone(),
+        accounts,
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(result), 0x232);
+
+    // a copy of the multisig at another address
+    let spoofed = fixture.spoof(multisig_address().0, id()).await;
+    let mut accounts = with_cosigner(fixture.withdraw_accounts(), cosigner.pubkey(), true);
+    accounts[6] = AccountMeta::new_readonly(spoofed, false);
+    let result = process(
+        &mut fixture.context,
+        withdraw.clone(),
+        accounts,
+        &[&fixture.admin, &cosigner],
+    )
+    .await;
+    assert_eq!(custom_error(result), 0x234);
+
+    let accounts = with_cosigner(fixture.withdraw_accounts(), cosigner.pubkey(), true);
+    process(
+        &mut fixture.context,
+        withdraw,
+        accounts,
+        &[&fixture.admin, &cosigner],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn regenerate_vault_needs_multisig_quorum() {
+    let mut fixture = Fixture::start(vault_data(), 0, 0).await;
+    let admin = fixture.admin.pubkey();
+    let cosigner = Keypair::new();
+    fixture.set_multisig(&[admin, cosigner.pubkey()], 2);
+
+    let (vault, _) = vault_address();
+    let (multisig, _) = multisig_address();
+    let payer = fixture.context.payer.pubkey();
+    let accounts = |signed: bool| {
+        vec![
+            AccountMeta::new(admin, true),
+            AccountMeta::new(payer, true),
+            AccountMeta::new(vault, false),
+            AccountMeta::new(token_address(&vault), false),
+            AccountMeta::new_readonly(MINT, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+            AccountMeta::new_readonly(multisig, false),
+            AccountMeta::new_readonly(cosigner.pubkey(), signed),
+        ]
+    };
+    let generate = StakeInstruction::GenerateVault {
+        min_period: DAY as u64,
+        reward_period: 3_600,
+        rate: 2_500,
+        early_withdrawal_fee: 0,
+        position_nft: false,
+        receipt_token: false,
+        share_accounting: false,
+        boost_collection: Pubkey::default(),
+        boost_bps: 0,
+        referral_bps: 0,
+        linear_accrual: false,
+        lock_policy: LOCK_EXTEND,
+        rollover_policy: ROLLOVER_COMPOUND,
+    };
+
+    // regenerating recreates the vault's token account
+    fixture
+        .context
+        .set_account(&token_address(&vault), &Account::default().into());
+
+    let result = process(
+        &mut fixture.context,
+        generate.clone(),
+        accounts(false),
+        &[&fixture.admin],
+    )
+    .await;
+    assert_eq!(custom_error(result), 0x232);
+
+    process(
+        &mut fixture.context,
+        generate,
+        accounts(true),
+        &[&fixture.admin, &cosigner],
+    )
+    .await
+    .unwrap();
+
+    let vault_data: VaultData =
+        bytemuck::pod_read_unaligned(&account_data(&mut fixture.context, vault).await);
+    assert_eq!((vault_data.rate, vault_data.multisig), (2_500, multisig));
+}
+
+#[tokio::test]
+async fn payout_rollover_counts_as_withdrawn() {
+    let vault_data = VaultData {
+        rollover_policy: ROLLOVER_PAYOUT,
+        ..vault_data()
+    };
+    let mut fixture = Fixture::start(vault_data, 1_000 * TOKEN, 0).await;
+    let open = fixture.stake(1_000 * TOKEN).await;
+    fixture.process(open).await.unwrap();
+
+    fixture.warp(30 * DAY).await;
+    let top_up = fixture.stake(TOKEN).await;
+    fixture.process(top_up).await.unwrap();
+
+    let staker = fixture.staker.pubkey();
+    let stake_data: StakeData = bytemuck::pod_read_unaligned(
+        &account_data(&mut fixture.context, stake_address(&staker).0).await,
+    );
+    let balance =
+        TokenAccount::unpack(&account_data(&mut fixture.context, token_address(&staker)).await)
+            .unwrap()
+            .amount;
+    assert!(stake_data.withdrawn > 0);
+    assert_eq!(stake_data.withdrawn, stake_data.harvested);
+    assert_eq!(
+        balance,
+        STAKER_BALANCE - 1_001 * TOKEN + stake_data.withdrawn
+    );
+}
@@ -0,0 +1,394 @@
+use bytemuck::Pod;
+use solana_program::{
+    account_info::{next_account_info, AccountInfo},
+    entrypoint::ProgramResult,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+};
+
+use std::mem::size_of;
+
+use crate::{
+    AccountKey, MultisigData, ReferralData, StakeData, VaultData, ADMIN_PK, NFT_VAULT_SEED,
+    VAULT_SEED,
+};
+
+// Program-owned account types, identified by their leading key byte.
+pub(crate) trait ProgramAccount: Pod {
+    const KEY: AccountKey;
+    // returned when the account has the right key but is too short for the layout
+    const DECODE_ERROR: u32;
+}
+
+impl ProgramAccount for StakeData {
+    const KEY: AccountKey = AccountKey::StakeV1;
+    const DECODE_ERROR: u32 = 0x913;
+}
+
+impl ProgramAccount for VaultData {
+    const KEY: AccountKey = AccountKey::VaultV1;
+    const DECODE_ERROR: u32 = 0x912;
+}
+
+impl ProgramAccount for ReferralData {
+    const KEY: AccountKey = AccountKey::ReferralV1;
+    const DECODE_ERROR: u32 = 0x914;
+}
+
+impl ProgramAccount for MultisigData {
+    const KEY: AccountKey = AccountKey::MultisigV1;
+    const DECODE_ERROR: u32 = 0x915;
+}
+
+// Loads a program account, rejecting accounts owned by another program or holding another type.
+pub(crate) fn load_account<T: ProgramAccount>(
+    account_info: &AccountInfo,
+    program_id: &Pubkey,
+) -> Result<T, ProgramError> {
+    if account_info.owner != program_id {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let data = account_info.try_borrow_data()?;
+    if data.first() != Some(&(T::KEY as u8)) {
+        //wrong account type
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    data.get(..size_of::<T>())
+        .map(bytemuck::pod_read_unaligned)
+        .ok_or(ProgramError::Custom(T::DECODE_ERROR))
+}
+
+// Writes a program account back over the start of its data.
+pub(crate) fn store_account<T: ProgramAccount>(
+    account_info: &AccountInfo,
+    value: &T,
+) -> ProgramResult {
+    let mut data = account_info.try_borrow_mut_data()?;
+    data.get_mut(..size_of::<T>())
+        .ok_or(ProgramError::AccountDataTooSmall)?
+        .copy_from_slice(bytemuck::bytes_of(value));
+
+    Ok(())
+}
+
+pub(crate) fn assert_writable(account_infos: &[&AccountInfo]) -> ProgramResult {
+    if account_infos.iter().any(|info| !info.is_writable) {
+        //account must be writable
+        return Err(ProgramError::Custom(0x16));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn assert_program(account_info: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
+    if account_info.key != program_id || !account_info.executable {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+// Checks `account_info` is the address of `seeds` with a stored `bump`, which avoids paying
+// for a bump search on every instruction.
+pub(crate) fn assert_pda(
+    account_info: &AccountInfo,
+    seeds: &[&[u8]],
+    bump: u8,
+    program_id: &Pubkey,
+    error: u32,
+) -> ProgramResult {
+    let bump = [bump];
+    let mut seeds = seeds.to_vec();
+    seeds.push(&bump);
+
+    match Pubkey::create_program_address(&seeds, program_id) {
+        Ok(address) if address == *account_info.key => Ok(()),
+        _ => Err(ProgramError::Custom(error)),
+    }
+}
+
+// Checks `vault_info` is the fungible or the nft vault, returning the seed it is derived from
+// for signing.
+pub(crate) fn assert_vault(
+    vault_info: &AccountInfo,
+    bump: u8,
+    program_id: &Pubkey,
+    error: u32,
+) -> Result<&'static [u8], ProgramError> {
+    if assert_pda(vault_info, &[VAULT_SEED], bump, program_id, error).is_ok() {
+        return Ok(VAULT_SEED);
+    }
+
+    assert_pda(vault_info, &[NFT_VAULT_SEED], bump, program_id, error)?;
+    Ok(NFT_VAULT_SEED)
+}
+
+// The vault's token account must cover every staker's principal plus every reserved reward.
+// Checked at the end of each instruction that moves vault funds or changes what they back.
+pub(crate) fn assert_solvent(
+    vault_data: &VaultData,
+    vault_token_account_info: &AccountInfo,
+) -> ProgramResult {
+    let balance =
+        spl_token::state::Account::unpack_from_slice(&vault_token_account_info.data.borrow())?
+            .amount;
+
+    // nft vaults escrow the staked nfts separately, their token account only backs rewards
+    let staked = if vault_data.collection == Pubkey::default() {
+        vault_data.total_staked
+    } else {
+        0
+    };
+
+    let required = match staked.checked_add(vault_data.total_obligations) {
+        Some(x) => x,
+        _ => return Err(ProgramError::Custom(0x109)),
+    };
+
+    if balance < required {
+        //vault would be insolvent
+        return Err(ProgramError::Custom(0x115));
+    }
+
+    Ok(())
+}
+
+// Authorises an admin instruction on a vault. Without a multisig `ADMIN_PK` must sign, failing
+// with `error`. Once one is set `ADMIN_PK` alone has no authority: the multisig account follows
+// the instruction's own accounts, then the co-signers, and `threshold` of its signers must sign.
+pub(crate) fn assert_admin<'a, 'b: 'a>(
+    program_id: &Pubkey,
+    admin_info: &AccountInfo<'b>,
+    multisig: &Pubkey,
+    accounts_iter: &mut std::slice::Iter<'a, AccountInfo<'b>>,
+    error: u32,
+) -> ProgramResult {
+    if *multisig == Pubkey::default() {
+        if *admin_info.key != ADMIN_PK || !admin_info.is_signer {
+            //unauthorized access
+            return Err(ProgramError::Custom(error));
+        }
+
+        return Ok(());
+    }
+
+    let multisig_info = next_account_info(accounts_iter)?;
+    if multisig_info.key != multisig {
+        //wrong multisig account
+        return Err(ProgramError::Custom(0x234));
+    }
+
+    let multisig_data: MultisigData = load_account(multisig_info, program_id)?;
+    assert_quorum(&multisig_data, admin_info, accounts_iter.as_slice())
+}
+
+// Counts the multisig's signers that signed, either as the admin account or as a co-signer.
+pub(crate) fn assert_quorum<'a>(
+    multisig_data: &MultisigData,
+    admin_info: &AccountInfo<'a>,
+    cosigner_infos: &[AccountInfo<'a>],
+) -> ProgramResult {
+    let signers = multisig_data
+        .signers
+        .get(..multisig_data.signer_count as usize)
+        .ok_or(ProgramError::Custom(MultisigData::DECODE_ERROR))?;
+
+    // signers are distinct, so a signer signing twice is still one approval
+    let approvals = signers
+        .iter()
+        .filter(|signer| {
+            std::iter::once(admin_info)
+                .chain(cosigner_infos)
+                .any(|info| info.is_signer && info.key == *signer)
+        })
+        .count();
+
+    if multisig_data.threshold == 0 || approvals < multisig_data.threshold as usize {
+        //not enough admin signatures
+        return Err(ProgramError::Custom(0x232));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AccountKey, VAULT_SEED};
+    use bytemuck::Zeroable;
+
+    fn stake_data() -> StakeData {
+        StakeData {
+            key: AccountKey::StakeV1 as u8,
+            staked_amount: 1_000,
+            staker: Pubkey::new_unique(),
+            ..StakeData::zeroed()
+        }
+    }
+
+    fn account_info<'a>(
+        key: &'a Pubkey,
+        is_writable: bool,
+        lamports: &'a mut u64,
+        data: &'a mut [u8],
+        owner: &'a Pubkey,
+    ) -> AccountInfo<'a> {
+        AccountInfo::new(key, false, is_writable, lamports, data, owner, false, 0)
+    }
+
+    #[test]
+    fn load_stored_account() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = vec![0; size_of::<StakeData>()];
+        let info = account_info(&key, true, &mut lamports, &mut data, &program_id);
+
+        store_account(&info, &stake_data()).unwrap();
+        let loaded: StakeData = load_account(&info, &program_id).unwrap();
+        assert_eq!(loaded.staked_amount, 1_000);
+    }
+
+    #[test]
+    fn load_rejects_wrong_owner() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = bytemuck::bytes_of(&stake_data()).to_vec();
+        let info = account_info(&key, true, &mut lamports, &mut data, &owner);
+
+        assert_eq!(
+            load_account::<StakeData>(&info, &program_id),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn load_rejects_wrong_account_key() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        // a stake account long enough to read as a vault
+        let mut data = bytemuck::bytes_of(&stake_data()).to_vec();
+        data.resize(size_of::<VaultData>(), 0);
+        let info = account_info(&key, true, &mut lamports, &mut data, &program_id);
+
+        assert_eq!(
+            load_account::<VaultData>(&info, &program_id),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            load_account::<ReferralData>(&info, &program_id),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn load_rejects_uninitialized_and_empty_accounts() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = bytemuck::bytes_of(&stake_data()).to_vec();
+        data[0] = AccountKey::Uninitialized as u8;
+        let info = account_info(&key, true, &mut lamports, &mut data, &program_id);
+
+        assert_eq!(
+            load_account::<StakeData>(&info, &program_id),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        let mut lamports = 0;
+        let mut data = [];
+        let info = account_info(&key, true, &mut lamports, &mut data, &program_id);
+
+        assert_eq!(
+            load_account::<StakeData>(&info, &program_id),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn load_rejects_truncated_account() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = bytemuck::bytes_of(&stake_data()).to_vec();
+        data.truncate(size_of::<StakeData>() - 1);
+        let info = account_info(&key, true, &mut lamports, &mut data, &program_id);
+
+        assert_eq!(
+            load_account::<StakeData>(&info, &program_id),
+            Err(ProgramError::Custom(StakeData::DECODE_ERROR))
+        );
+        assert_eq!(
+            store_account(&info, &stake_data()),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+    }
+
+    #[test]
+    fn writable() {
+        let owner = Pubkey::new_unique();
+        let (key, other_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut lamports, mut other_lamports) = (0, 0);
+        let (mut data, mut other_data) = ([0; 8], [0; 8]);
+        let writable = account_info(&key, true, &mut lamports, &mut data, &owner);
+        let read_only = account_info(
+            &other_key,
+            false,
+            &mut other_lamports,
+            &mut other_data,
+            &owner,
+        );
+
+        assert_eq!(assert_writable(&[&writable]), Ok(()));
+        assert_eq!(
+            assert_writable(&[&writable, &read_only]),
+            Err(ProgramError::Custom(0x16))
+        );
+    }
+
+    #[test]
+    fn pda() {
+        let program_id = Pubkey::new_unique();
+        let (vault, bump) = Pubkey::find_program_address(&[VAULT_SEED], &program_id);
+        let mut lamports = 0;
+        let mut data = [];
+        let info = account_info(&vault, true, &mut lamports, &mut data, &program_id);
+
+        assert_eq!(
+            assert_pda(&info, &[VAULT_SEED], bump, &program_id, 0x12),
+            Ok(())
+        );
+        // every other bump derives another address, or none
+        for wrong_bump in (0..=u8::MAX).filter(|x| *x != bump) {
+            assert_eq!(
+                assert_pda(&info, &[VAULT_SEED], wrong_bump, &program_id, 0x12),
+                Err(ProgramError::Custom(0x12))
+            );
+        }
+        assert_eq!(
+            assert_pda(&info, &[b"referral"], bump, &program_id, 0x12),
+            Err(ProgramError::Custom(0x12))
+        );
+        assert_eq!(
+            assert_pda(&info, &[VAULT_SEED], bump, &Pubkey::new_unique(), 0x12),
+            Err(ProgramError::Custom(0x12))
+        );
+
+        // an account that isn't the pda at all
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = [];
+        let info = account_info(&key, true, &mut lamports, &mut data, &program_id);
+
+        assert_eq!(
+            assert_pda(&info, &[VAULT_SEED], bump, &program_id, 0x12),
+            Err(ProgramError::Custom(0x12))
+        );
+    }
+}